@@ -0,0 +1,1122 @@
+use color_eyre::Result;
+use crossterm::event::KeyModifiers;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::widgets::{Scrollbar, ScrollbarOrientation, ScrollbarState};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, Clear, LineGauge, Paragraph, Wrap},
+};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::devices::{InputDevice, input_devices};
+use crate::export::export_subtitles;
+use crate::glossary::GlossaryEntry;
+use crate::language::LanguagePair;
+use crate::loading::{LoadProgress, LoadStage};
+use crate::pipeline::{PipelineCommand, PipelineState};
+use crate::segment::{Segment, SegmentId, TranslationStatus};
+use crate::session::{Session, SessionModels};
+use crate::translator::Translation;
+use crate::udp::StreamHealth;
+
+/// Messages sent from the processing pipeline to the UI.
+#[derive(Debug)]
+pub enum AppUpdate {
+    LiveTranscriptUpdate(String),
+    SegmentComplete(Segment),
+    /// The translation generated so far, while the segment is still being translated.
+    TranslationPartial(SegmentId, String),
+    Translation(SegmentId, Translation),
+    TranslationFailed(SegmentId, String),
+    /// Glossary terms the finished translation of the segment didn't use.
+    GlossaryViolations(SegmentId, Vec<GlossaryEntry>),
+    /// The segment was dropped from the translation queue to keep up with live speech.
+    TranslationSkipped(SegmentId),
+    /// `from` was queued behind `into` and will be translated with it; fold it in.
+    SegmentsMerged {
+        into: SegmentId,
+        from: SegmentId,
+    },
+    /// Segments waiting for or undergoing translation.
+    TranslationQueueDepth(usize),
+    PipelineStateChanged(PipelineState),
+    /// The models the pipeline is running with, for the saved session.
+    ModelsLoaded(SessionModels),
+    /// How far one model has got with downloading and loading.
+    ModelLoadProgress(LoadProgress),
+    SamplesProcessed(usize),
+    RawSamplesDetected(usize),
+    /// The capture moved to the input with this label.
+    InputDeviceChanged(String),
+    /// Packet rate and loss of a network input, about once a second.
+    StreamHealth(StreamHealth),
+    /// How far through a finite input (such as a file) the pipeline has read.
+    InputProgress {
+        position: Duration,
+        total: Duration,
+    },
+    StatusUpdate(String),
+    Error(String),
+    /// The pipeline stopped with this error; nothing more is coming.
+    PipelineFailed(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AppInputMode {
+    Listening,
+    StoppedTyping,
+}
+
+/// The list of input devices opened with Ctrl+D.
+struct DevicePicker {
+    devices: Vec<InputDevice>,
+    selected: usize,
+}
+
+/// UI state, driven by [`AppUpdate`]s from the pipeline and key events from the terminal.
+pub struct App {
+    pub status: String,
+    pub current_live_transcript: String,
+    /// Finished segments, oldest first.
+    pub segments: Vec<Segment>,
+    rx: mpsc::Receiver<AppUpdate>,
+    pub should_quit: bool,
+    pub input_mode: AppInputMode,
+    pub user_input: String, // For when typing is enabled
+    /// Commands to the audio processing task.
+    control_tx: mpsc::Sender<PipelineCommand>,
+    /// The pipeline's own report of what it is doing, which may lag behind `input_mode`.
+    pub pipeline_state: PipelineState,
+    source_scroll_state: ScrollbarState,
+    source_scroll: usize,
+    translation_scroll_state: ScrollbarState,
+    translation_scroll: u16,
+    total_samples_listened: usize,
+    raw_samples_count: usize,
+    /// Shown in the live input title, e.g. "Microphone" or a file name.
+    input_label: String,
+    input_progress: Option<(Duration, Duration)>,
+    /// Drives the panel titles and the subtitle file names.
+    languages: LanguagePair,
+    /// Subtitles are written next to this path, as `<base>.<lang>.srt` and `.vtt`.
+    export_base: PathBuf,
+    /// Export by itself once the pipeline finishes.
+    auto_export: bool,
+    /// Quit once the pipeline finishes, for input that can't be replayed such as a pipe.
+    quit_when_finished: bool,
+    /// Where Ctrl+S saves the session, if anywhere.
+    session_path: Option<PathBuf>,
+    models: SessionModels,
+    /// One entry per model, in the order they were first reported, while the pipeline loads.
+    model_loads: Vec<LoadProgress>,
+    /// No translation model is loaded; only the transcript is shown, saved and exported.
+    transcribe_only: bool,
+    /// The input is a capture device, which Ctrl+D can switch.
+    device_switching: bool,
+    device_picker: Option<DevicePicker>,
+    /// The latest report from a network input, shown next to the input label.
+    stream_health: Option<StreamHealth>,
+    translation_queue_depth: usize,
+    /// Set once the pipeline has stopped with an error.
+    pub pipeline_error: Option<String>,
+}
+
+impl App {
+    pub fn new(rx: mpsc::Receiver<AppUpdate>, control_tx: mpsc::Sender<PipelineCommand>) -> Self {
+        Self {
+            status: "Initializing... Press 's' to Stop/Start, 'q' to Quit".to_string(),
+            current_live_transcript: String::new(),
+            segments: Vec::new(),
+            rx,
+            should_quit: false,
+            input_mode: AppInputMode::Listening,
+            user_input: String::new(),
+            control_tx,
+            pipeline_state: PipelineState::Loading,
+            source_scroll_state: ScrollbarState::default(),
+            source_scroll: 0,
+            translation_scroll_state: ScrollbarState::default(),
+            translation_scroll: 0,
+            total_samples_listened: 0,
+            raw_samples_count: 0,
+            input_label: "Microphone".to_string(),
+            input_progress: None,
+            languages: LanguagePair::default(),
+            export_base: PathBuf::from("transvibe"),
+            auto_export: false,
+            quit_when_finished: false,
+            session_path: None,
+            models: SessionModels::default(),
+            model_loads: Vec::new(),
+            transcribe_only: false,
+            device_switching: false,
+            device_picker: None,
+            stream_health: None,
+            translation_queue_depth: 0,
+            pipeline_error: None,
+        }
+    }
+
+    pub fn with_languages(mut self, languages: LanguagePair) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    pub fn with_export_base(mut self, base: impl Into<PathBuf>, auto_export: bool) -> Self {
+        self.export_base = base.into();
+        self.auto_export = auto_export;
+        self
+    }
+
+    /// Quits as soon as all the input has been transcribed and translated.
+    pub fn with_quit_when_finished(mut self, quit_when_finished: bool) -> Self {
+        self.quit_when_finished = quit_when_finished;
+        self
+    }
+
+    /// Saves the session to `path` on Ctrl+S.
+    pub fn with_session_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.session_path = Some(path.into());
+        self
+    }
+
+    /// Starts from a previously saved session, whose segments stay in the history.
+    pub fn with_session(mut self, session: &Session) -> Self {
+        self.segments = session.to_segments();
+        self.models = session.models.clone();
+        self
+    }
+
+    /// Shows only the transcript, for a pipeline running without a translator.
+    pub fn with_transcribe_only(mut self, transcribe_only: bool) -> Self {
+        self.transcribe_only = transcribe_only;
+        self
+    }
+
+    /// Lets Ctrl+D move the capture to another input device.
+    pub fn with_device_switching(mut self, device_switching: bool) -> Self {
+        self.device_switching = device_switching;
+        self
+    }
+
+    pub fn with_input_label(mut self, label: impl Into<String>) -> Self {
+        self.input_label = label.into();
+        self
+    }
+
+    pub fn on_update(&mut self, update: AppUpdate) {
+        match update {
+            AppUpdate::StatusUpdate(s) => self.status = s,
+            AppUpdate::LiveTranscriptUpdate(s) => self.current_live_transcript = s,
+            AppUpdate::SegmentComplete(segment) => {
+                self.segments.push(segment);
+                self.current_live_transcript.clear();
+            }
+            AppUpdate::TranslationPartial(id, partial) => {
+                if let Some(segment) = self.segment_mut(id)
+                    && segment.status == TranslationStatus::Pending
+                {
+                    segment.translation = partial;
+                }
+            }
+            AppUpdate::Translation(id, translation) => {
+                let source_language = self.languages.source;
+                if let Some(segment) = self.segment_mut(id) {
+                    segment.translation = translation.text;
+                    segment.notes = translation.notes;
+                    // Only worth showing when it disagrees with what we told Whisper.
+                    segment.detected_language = translation
+                        .detected_language
+                        .filter(|language| *language != source_language);
+                    segment.status = TranslationStatus::Done;
+                }
+            }
+            AppUpdate::TranslationFailed(id, err_msg) => {
+                if let Some(segment) = self.segment_mut(id) {
+                    segment.status = TranslationStatus::Failed;
+                    segment.translation.clear(); // Drop any partial output
+                }
+                self.status = format!("ERROR: Translation of segment {} failed: {}", id, err_msg);
+            }
+            AppUpdate::GlossaryViolations(id, violations) => {
+                if let Some(segment) = self.segment_mut(id) {
+                    segment.glossary_violations = violations;
+                }
+            }
+            AppUpdate::TranslationSkipped(id) => {
+                if let Some(segment) = self.segment_mut(id) {
+                    segment.status = TranslationStatus::Skipped;
+                    segment.translation.clear();
+                }
+            }
+            AppUpdate::SegmentsMerged { into, from } => {
+                if let Some(index) = self.segments.iter().rposition(|segment| segment.id == from) {
+                    let later = self.segments.remove(index);
+                    if let Some(segment) = self.segment_mut(into) {
+                        segment.absorb(later);
+                    }
+                }
+            }
+            AppUpdate::TranslationQueueDepth(depth) => self.translation_queue_depth = depth,
+            AppUpdate::PipelineStateChanged(state) => {
+                self.pipeline_state = state;
+                if state == PipelineState::Paused {
+                    // Whatever was captured before the pause has been thrown away.
+                    self.raw_samples_count = 0;
+                    self.current_live_transcript.clear();
+                }
+                if state == PipelineState::Finished && self.auto_export {
+                    self.export();
+                }
+                if state == PipelineState::Finished && self.quit_when_finished {
+                    self.should_quit = true;
+                }
+            }
+            AppUpdate::ModelsLoaded(models) => self.models = models,
+            AppUpdate::ModelLoadProgress(progress) => {
+                match self
+                    .model_loads
+                    .iter_mut()
+                    .find(|load| load.model == progress.model)
+                {
+                    Some(load) => *load = progress,
+                    None => self.model_loads.push(progress),
+                }
+            }
+            AppUpdate::SamplesProcessed(samples) => {
+                self.total_samples_listened += samples;
+                self.raw_samples_count = 0; // Reset after final samples for transcribed segment reported
+            }
+            AppUpdate::RawSamplesDetected(samples) => {
+                self.raw_samples_count += samples;
+            }
+            AppUpdate::InputDeviceChanged(device) => {
+                self.status = format!("Listening to {}", device);
+                self.input_label = device;
+            }
+            AppUpdate::StreamHealth(health) => self.stream_health = Some(health),
+            AppUpdate::InputProgress { position, total } => {
+                self.input_progress = Some((position, total));
+            }
+            AppUpdate::Error(err_msg) => {
+                self.status = format!("ERROR: {}", err_msg);
+                // Potentially log to a file or display more prominently
+            }
+            AppUpdate::PipelineFailed(error) => {
+                self.status = format!("ERROR: {}", error);
+                self.pipeline_error = Some(error);
+            }
+        }
+    }
+
+    /// Looks up a segment by id, searching from the newest since that is where updates usually land.
+    pub fn segment_mut(&mut self, id: SegmentId) -> Option<&mut Segment> {
+        self.segments
+            .iter_mut()
+            .rev()
+            .find(|segment| segment.id == id)
+    }
+
+    /// Waits for the next message from the pipeline; `None` once the pipeline has gone away.
+    pub async fn next_update(&mut self) -> Option<AppUpdate> {
+        self.rx.recv().await
+    }
+
+    pub fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
+        while !self.should_quit {
+            terminal.draw(|frame| self.render(frame))?;
+            self.handle_events()?;
+            self.handle_updates();
+        }
+        Ok(())
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        if event::poll(std::time::Duration::from_millis(50))?
+            && let Event::Key(key) = event::read()?
+        {
+            self.handle_key(key);
+        }
+        Ok(())
+    }
+
+    /// Applies a single key event to the UI state.
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if self.device_picker.is_some() {
+            self.handle_device_picker_key(key);
+            return;
+        }
+        // Global keybindings for scrolling, etc.
+        // Check for scroll events first, as they are global.
+        let mut event_handled = true; // Assume handled if it matches
+        match (key.code, key.modifiers) {
+            (KeyCode::Down, KeyModifiers::CONTROL)
+            | (KeyCode::Char('j'), KeyModifiers::CONTROL) => {
+                self.scroll_translation_down();
+            }
+            (KeyCode::Up, KeyModifiers::CONTROL) | (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
+                self.scroll_translation_up();
+            }
+            (KeyCode::Down, KeyModifiers::ALT) | (KeyCode::Char('j'), KeyModifiers::ALT) => {
+                self.scroll_source_down();
+            }
+            (KeyCode::Up, KeyModifiers::ALT) | (KeyCode::Char('k'), KeyModifiers::ALT) => {
+                self.scroll_source_up();
+            }
+            (KeyCode::Char('e'), KeyModifiers::CONTROL) => {
+                self.export();
+            }
+            (KeyCode::Char('s'), KeyModifiers::CONTROL) => {
+                self.save_session();
+            }
+            (KeyCode::Char('d'), KeyModifiers::CONTROL) => {
+                self.open_device_picker();
+            }
+            _ => {
+                event_handled = false; // Not a global scroll key
+            }
+        }
+
+        if event_handled {
+            return;
+        }
+
+        // Mode-specific keybindings
+        if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
+            self.should_quit = true;
+            return;
+        }
+        match self.input_mode {
+            AppInputMode::Listening => match key.code {
+                KeyCode::Char('q') => {
+                    self.should_quit = true;
+                    self.status = "Exiting...".to_string();
+                }
+                KeyCode::Char('s') => {
+                    self.input_mode = AppInputMode::StoppedTyping;
+                    self.send_command(PipelineCommand::Pause);
+                    self.status =
+                        "Stopped. Press 's' to Start. Type your message, Enter to process."
+                            .to_string();
+                    self.current_live_transcript.clear(); // Clear live transcription
+                    self.user_input.clear(); // Clear previous user input
+                }
+                _ => {}
+            },
+            AppInputMode::StoppedTyping => match key.code {
+                KeyCode::Char('q') => {
+                    self.should_quit = true;
+                    self.status = "Exiting...".to_string();
+                }
+                KeyCode::Char('s') => {
+                    self.input_mode = AppInputMode::Listening;
+                    self.send_command(PipelineCommand::Resume);
+                    self.status = "Starting... Press 's' to Stop/Start, 'q' to Quit".to_string();
+                    self.user_input.clear();
+                }
+                KeyCode::Enter if self.transcribe_only => {
+                    self.status = "Translation is off in transcribe-only mode.".to_string();
+                }
+                // Typed text is translated by the pipeline and comes back as a segment.
+                KeyCode::Enter if !self.user_input.trim().is_empty() => {
+                    self.status = format!(
+                        "Input '{}' submitted. Press 's' to start listening.",
+                        self.user_input
+                    );
+                    let text = std::mem::take(&mut self.user_input);
+                    self.send_command(PipelineCommand::TranslateText(text));
+                }
+                KeyCode::Char(c) => {
+                    self.user_input.push(c);
+                }
+                KeyCode::Backspace => {
+                    self.user_input.pop();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Lists the input devices to choose from, starting at the one in use.
+    pub fn open_device_picker(&mut self) {
+        if !self.device_switching {
+            self.status = "Only live capture input can switch devices".to_string();
+            return;
+        }
+        match input_devices() {
+            Ok(devices) if devices.is_empty() => {
+                self.status = "ERROR: No audio input devices found".to_string();
+            }
+            Ok(devices) => {
+                let selected = devices
+                    .iter()
+                    .position(|device| device.endpoint.to_string() == self.input_label)
+                    .or_else(|| devices.iter().position(|device| device.is_default))
+                    .unwrap_or(0);
+                self.device_picker = Some(DevicePicker { devices, selected });
+            }
+            Err(e) => self.status = format!("ERROR: {:#}", e),
+        }
+    }
+
+    fn handle_device_picker_key(&mut self, key: KeyEvent) {
+        let Some(picker) = &mut self.device_picker else {
+            return;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => picker.selected = picker.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                picker.selected = (picker.selected + 1).min(picker.devices.len() - 1);
+            }
+            KeyCode::Enter => {
+                let endpoint = picker.devices[picker.selected].endpoint.clone();
+                self.device_picker = None;
+                self.status = format!("Switching to {}...", endpoint);
+                self.send_command(PipelineCommand::SwitchDevice(endpoint));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.device_picker = None,
+            _ => {}
+        }
+    }
+
+    /// Writes source and translated subtitles for the session so far.
+    pub fn export(&mut self) {
+        match export_subtitles(
+            &self.segments,
+            self.languages,
+            self.transcribe_only,
+            &self.export_base,
+        ) {
+            Ok(paths) => {
+                self.status = format!(
+                    "Exported {} subtitle files to {}.*",
+                    paths.len(),
+                    self.export_base.display()
+                );
+            }
+            Err(e) => self.status = format!("ERROR: Export failed: {:#}", e),
+        }
+    }
+
+    /// The session so far, ready to be saved.
+    pub fn session(&self) -> Session {
+        Session::new(
+            self.languages,
+            self.models.clone(),
+            self.transcribe_only,
+            &self.segments,
+        )
+    }
+
+    /// Saves the session to the configured path.
+    pub fn save_session(&mut self) {
+        let Some(path) = &self.session_path else {
+            self.status = "ERROR: No session file configured".to_string();
+            return;
+        };
+        match self.session().save(path) {
+            Ok(()) => self.status = format!("Saved session to {}", path.display()),
+            Err(e) => self.status = format!("ERROR: Saving session failed: {:#}", e),
+        }
+    }
+
+    /// Asks the pipeline to stop capturing and finish what it has, as before exiting.
+    pub fn stop_pipeline(&mut self) {
+        self.send_command(PipelineCommand::Stop);
+    }
+
+    fn send_command(&mut self, command: PipelineCommand) {
+        if self.control_tx.try_send(command).is_err() {
+            self.status = "ERROR: Audio processing task is not accepting commands".to_string();
+        }
+    }
+
+    pub fn handle_updates(&mut self) {
+        while let Ok(update) = self.rx.try_recv() {
+            self.on_update(update);
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        // Until the pipeline is ready, the live input box shows a gauge per model instead.
+        // Models still loading after that (the translator, usually) keep a gauge above it.
+        let show_loading =
+            self.pipeline_state == PipelineState::Loading && !self.model_loads.is_empty();
+        let loads: Vec<&LoadProgress> = self
+            .model_loads
+            .iter()
+            .filter(|load| show_loading || load.stage != LoadStage::Ready)
+            .collect();
+        let loads_height = if loads.is_empty() {
+            0
+        } else {
+            loads.len() as u16 + 2
+        };
+        let input_height = if show_loading { 0 } else { 3 };
+        let main_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(1),            // Status
+                Constraint::Length(loads_height), // Model loading
+                Constraint::Length(input_height), // Live transcript
+                Constraint::Min(0),               // History
+            ])
+            .split(frame.area());
+
+        // General Status/Help Message
+        let queue_text = match self.translation_queue_depth {
+            0 => String::new(),
+            depth => format!(" [{} to translate]", depth),
+        };
+        let health_text = match &self.stream_health {
+            Some(health) => format!(": {}", health),
+            None => String::new(),
+        };
+        let help_text = match self.input_mode {
+            AppInputMode::Listening => {
+                format!(
+                    "[{}] [{}{}]{} Status: {} ({} samples processed) (Press 's' to Stop, Ctrl+E to Export, Ctrl+S to Save, 'q' to Quit)",
+                    self.pipeline_state,
+                    self.input_label,
+                    health_text,
+                    queue_text,
+                    self.status,
+                    self.total_samples_listened
+                )
+            }
+            AppInputMode::StoppedTyping if self.transcribe_only => {
+                format!(
+                    "[{}] Status: {} (Press 's' to Start, 'q' to Quit)",
+                    self.pipeline_state, self.status
+                )
+            }
+            AppInputMode::StoppedTyping => {
+                format!(
+                    "[{}]{} Status: {} (Press 's' to Start, 'q' to Quit, Enter to translate typed text)",
+                    self.pipeline_state, queue_text, self.status
+                )
+            }
+        };
+        let help_paragraph = Paragraph::new(help_text).style(Style::default().fg(Color::Yellow));
+        if let Some((position, total)) = self.input_progress {
+            let status_layout = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Min(0), Constraint::Length(40)])
+                .split(main_layout[0]);
+            frame.render_widget(help_paragraph, status_layout[0]);
+            let ratio = if total.is_zero() {
+                0.0
+            } else {
+                (position.as_secs_f64() / total.as_secs_f64()).clamp(0.0, 1.0)
+            };
+            let progress_gauge = LineGauge::default()
+                .filled_style(Style::default().fg(Color::Green))
+                .label(format!(
+                    "{} / {}",
+                    format_clock(position),
+                    format_clock(total)
+                ))
+                .ratio(ratio);
+            frame.render_widget(progress_gauge, status_layout[1]);
+        } else {
+            frame.render_widget(help_paragraph, main_layout[0]);
+        }
+
+        if !loads.is_empty() {
+            self.render_model_loads(frame, &loads, main_layout[1]);
+        }
+        if !show_loading {
+            self.render_input_area(frame, main_layout[2]);
+        }
+        self.render_history(frame, main_layout[3]);
+        if let Some(picker) = &self.device_picker {
+            render_device_picker(frame, picker);
+        }
+    }
+
+    /// One gauge per model: bytes and ETA while downloading, percent while loading.
+    fn render_model_loads(&self, frame: &mut Frame, loads: &[&LoadProgress], area: Rect) {
+        let block = Block::default()
+            .title("Loading models")
+            .borders(Borders::ALL);
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(1); loads.len()])
+            .split(block.inner(area));
+        frame.render_widget(block, area);
+        for (load, row) in loads.iter().zip(rows.iter()) {
+            let color = match load.stage {
+                LoadStage::Ready => Color::Green,
+                _ => Color::Cyan,
+            };
+            let gauge = LineGauge::default()
+                .filled_style(Style::default().fg(color))
+                .label(describe_load(load))
+                .ratio(load.ratio());
+            frame.render_widget(gauge, *row);
+        }
+    }
+
+    /// The live transcript, or the text being typed while stopped.
+    fn render_input_area(&self, frame: &mut Frame, area: Rect) {
+        // Input Area (Live transcript or User Text Input)
+        let input_area_title = match self.input_mode {
+            AppInputMode::Listening => format!(
+                "Live {} Input ({}, {})",
+                self.languages.source, self.input_label, self.pipeline_state
+            ),
+            AppInputMode::StoppedTyping => "Text Input (Stopped - Type here)".to_string(),
+        };
+        let input_block = Block::default()
+            .title(input_area_title)
+            .borders(Borders::ALL);
+
+        let text_to_display_in_input_area = match self.input_mode {
+            AppInputMode::Listening => self.current_live_transcript.as_str(),
+            AppInputMode::StoppedTyping => self.user_input.as_str(),
+        };
+
+        let mut text_widget = Paragraph::new(text_to_display_in_input_area)
+            .wrap(Wrap { trim: true })
+            .block(input_block.clone());
+
+        if self.input_mode == AppInputMode::StoppedTyping {
+            text_widget = text_widget.style(Style::default().fg(Color::Cyan)); // Style for typing mode
+            // Set cursor position for typing mode
+            #[allow(clippy::cast_possible_truncation)]
+            frame.set_cursor_position(Position::new(
+                area.x + self.user_input.chars().count() as u16 + 1,
+                area.y + 1,
+            ));
+        } else if self.current_live_transcript.is_empty()
+            && self.input_mode == AppInputMode::Listening
+            && self.pipeline_state == PipelineState::Listening
+        {
+            let listening_text = format!(
+                "Listening... ({} samples processed this segment)",
+                self.raw_samples_count
+            );
+            let listening_placeholder = Paragraph::new(listening_text)
+                .wrap(Wrap { trim: true })
+                .block(input_block)
+                .style(Style::default().add_modifier(Modifier::ITALIC));
+            frame.render_widget(listening_placeholder, area);
+        } else {
+            frame.render_widget(text_widget.clone(), area);
+        }
+        // If it's StoppedTyping mode, render text_widget again to ensure cursor is handled correctly
+        // This is a bit redundant but ensures the cursor logic from above is effective
+        // This is needed because we might have rendered the "Listening..." placeholder.
+        if self.input_mode == AppInputMode::StoppedTyping {
+            frame.render_widget(text_widget, area);
+        }
+    }
+
+    /// The transcript and translation panels, or the transcript alone when transcribing only.
+    fn render_history(&mut self, frame: &mut Frame, area: Rect) {
+        let panel_widths = if self.transcribe_only {
+            vec![Constraint::Percentage(100)]
+        } else {
+            vec![Constraint::Percentage(50), Constraint::Percentage(50)]
+        };
+        let history_layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(panel_widths)
+            .split(area);
+
+        // Source Transcript Panel
+        let source_lines: Vec<Line> = self
+            .segments
+            .iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(i, segment)| {
+                let style = if i == 0 {
+                    // Highlight the first line (newest)
+                    Style::new().fg(Color::White)
+                } else {
+                    Style::new().fg(Color::DarkGray)
+                };
+                let content_line = Line::from(segment.source.as_str()).style(style);
+                if i == 0 {
+                    // Newest item, don't add preceding blank line
+                    vec![content_line]
+                } else {
+                    // Add a blank line before older items for separation
+                    vec![Line::from(""), content_line]
+                }
+            })
+            .collect();
+
+        let source_block = Block::default()
+            .title(format!("{} Transcript", self.languages.source))
+            .borders(Borders::ALL);
+        let common_wrap_setting = Wrap { trim: true };
+
+        // Set scrollbar content length to number of items
+        self.source_scroll_state = self.source_scroll_state.content_length(self.segments.len());
+
+        // Auto-scroll logic removed. Scrolling is now manual.
+
+        let source_paragraph = Paragraph::new(source_lines)
+            .block(source_block)
+            .wrap(common_wrap_setting)
+            .scroll((self.source_scroll as u16, 0));
+        frame.render_widget(source_paragraph, history_layout[0]);
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(Some("↑"))
+                .end_symbol(Some("↓")),
+            history_layout[0], // Render scrollbar in the same area
+            &mut self.source_scroll_state,
+        );
+        if self.transcribe_only {
+            return;
+        }
+
+        // Translation Panel
+        let translation_lines: Vec<Line> = self
+            .segments
+            .iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(i, segment)| {
+                let style = if i == 0 {
+                    // Highlight the first line (newest)
+                    Style::new().fg(Color::White)
+                } else {
+                    Style::new().fg(Color::DarkGray)
+                };
+                let content_line = Line::from(segment.translation_display()).style(style);
+                let mut lines = if i == 0 {
+                    // Newest item
+                    vec![content_line]
+                } else {
+                    // Add a blank line before older items
+                    vec![Line::from(""), content_line]
+                };
+                if let Some(language) = segment.detected_language {
+                    lines.push(
+                        Line::from(format!("(Sounds like {})", language)).style(
+                            Style::new()
+                                .fg(Color::DarkGray)
+                                .add_modifier(Modifier::ITALIC),
+                        ),
+                    );
+                }
+                if let Some(notes) = &segment.notes {
+                    lines.push(
+                        Line::from(format!("Note: {}", notes)).style(
+                            Style::new()
+                                .fg(Color::DarkGray)
+                                .add_modifier(Modifier::ITALIC),
+                        ),
+                    );
+                }
+                if !segment.glossary_violations.is_empty() {
+                    let terms: Vec<String> = segment
+                        .glossary_violations
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect();
+                    lines.push(
+                        Line::from(format!("⚠ Glossary: {}", terms.join(", ")))
+                            .style(Style::new().fg(Color::Red)),
+                    );
+                }
+                lines
+            })
+            .collect();
+
+        let translation_block = Block::default()
+            .title(format!("{} Translation", self.languages.target))
+            .borders(Borders::ALL);
+        // Assuming same wrap setting as transcript panel, can be customized if needed
+        let common_wrap_setting = Wrap { trim: true };
+
+        // Set scrollbar content length to number of items
+        self.translation_scroll_state = self
+            .translation_scroll_state
+            .content_length(self.segments.len());
+
+        // Auto-scroll logic removed. Scrolling is now manual.
+
+        let translation_paragraph = Paragraph::new(translation_lines)
+            .block(translation_block)
+            .wrap(common_wrap_setting)
+            .scroll((self.translation_scroll, 0));
+        frame.render_widget(translation_paragraph, history_layout[1]);
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(Some("↑"))
+                .end_symbol(Some("↓")),
+            history_layout[1], // Render scrollbar in the same area
+            &mut self.translation_scroll_state,
+        );
+    }
+
+    fn scroll_source_down(&mut self) {
+        let content_height = self.segments.len();
+        // Assuming roughly one line per item for simplicity in limiting scroll.
+        // A more precise calculation might involve the actual rendered height if lines wrap.
+        if content_height > 0 {
+            // Check if there's content to scroll
+            self.source_scroll = self.source_scroll.saturating_add(1);
+            // Prevent scrolling beyond content. The paragraph widget itself might also clamp this.
+            // This is a basic clamp; true max scroll depends on viewport height vs content height.
+            if self.source_scroll >= content_height {
+                self.source_scroll = content_height.saturating_sub(1);
+            }
+        }
+        self.source_scroll_state = self.source_scroll_state.position(self.source_scroll);
+    }
+
+    fn scroll_source_up(&mut self) {
+        self.source_scroll = self.source_scroll.saturating_sub(1);
+        self.source_scroll_state = self.source_scroll_state.position(self.source_scroll);
+    }
+
+    fn scroll_translation_down(&mut self) {
+        let content_height = self.segments.len() as u16;
+        if content_height > 0 {
+            self.translation_scroll = self.translation_scroll.saturating_add(1);
+            if self.translation_scroll >= content_height {
+                self.translation_scroll = content_height.saturating_sub(1);
+            }
+        }
+        self.translation_scroll_state = self
+            .translation_scroll_state
+            .position(self.translation_scroll as usize);
+    }
+
+    fn scroll_translation_up(&mut self) {
+        self.translation_scroll = self.translation_scroll.saturating_sub(1);
+        self.translation_scroll_state = self
+            .translation_scroll_state
+            .position(self.translation_scroll as usize);
+    }
+}
+
+/// The device list, in a box over the middle of the screen.
+fn render_device_picker(frame: &mut Frame, picker: &DevicePicker) {
+    let lines: Vec<Line> = picker
+        .devices
+        .iter()
+        .enumerate()
+        .map(|(i, device)| {
+            let line = Line::from(device.to_string());
+            if i == picker.selected {
+                line.style(Style::new().fg(Color::Black).bg(Color::Cyan))
+            } else {
+                line
+            }
+        })
+        .collect();
+    let area = frame.area();
+    let width = area.width.min(70);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let block = Block::default()
+        .title("Input device (Enter to switch, Esc to cancel)")
+        .borders(Borders::ALL);
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// `<model>: <what it is doing>`, with bytes and ETA while downloading and percent while
+/// loading.
+pub(crate) fn describe_load(load: &LoadProgress) -> String {
+    let detail = match &load.stage {
+        LoadStage::Waiting => "waiting".to_string(),
+        LoadStage::Downloading {
+            file,
+            downloaded,
+            total,
+            eta,
+        } => {
+            let eta = eta
+                .map(|eta| format!(", {} left", format_clock(eta)))
+                .unwrap_or_default();
+            format!(
+                "downloading {} {} / {} ({:.0}%{})",
+                file,
+                format_bytes(*downloaded),
+                format_bytes(*total),
+                load.ratio() * 100.0,
+                eta
+            )
+        }
+        LoadStage::Loading(_) => format!("loading ({:.0}%)", load.ratio() * 100.0),
+        LoadStage::Ready => "ready".to_string(),
+    };
+    format!("{}: {}", load.model, detail)
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.4 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` past the hour.
+pub(crate) fn format_clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An app whose commands end up in the returned receiver.
+    fn app() -> (App, mpsc::Receiver<PipelineCommand>) {
+        let (_, rx) = mpsc::channel(1);
+        let (control_tx, control_rx) = mpsc::channel(8);
+        (App::new(rx, control_tx), control_rx)
+    }
+
+    fn complete(app: &mut App, source: &str) -> SegmentId {
+        let id = SegmentId::next();
+        app.on_update(AppUpdate::SegmentComplete(Segment::new(
+            id,
+            source.to_string(),
+        )));
+        id
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    #[test]
+    fn translations_find_their_segment_whatever_order_they_finish_in() {
+        let (mut app, _commands) = app();
+        let first = complete(&mut app, "一つ");
+        let second = complete(&mut app, "二つ");
+        app.on_update(AppUpdate::Translation(second, Translation::new("two")));
+        app.on_update(AppUpdate::Translation(first, Translation::new("one")));
+
+        let translations: Vec<&str> = app
+            .segments
+            .iter()
+            .map(|segment| segment.translation.as_str())
+            .collect();
+        assert_eq!(translations, ["one", "two"]);
+        assert!(
+            app.segments
+                .iter()
+                .all(|segment| segment.status == TranslationStatus::Done)
+        );
+    }
+
+    #[test]
+    fn late_partials_do_not_overwrite_a_finished_translation() {
+        let (mut app, _commands) = app();
+        let id = complete(&mut app, "一つ");
+        app.on_update(AppUpdate::TranslationPartial(id, "on".to_string()));
+        assert_eq!(app.segments[0].translation, "on");
+
+        app.on_update(AppUpdate::Translation(id, Translation::new("one")));
+        app.on_update(AppUpdate::TranslationPartial(id, "on".to_string()));
+        assert_eq!(app.segments[0].translation, "one");
+    }
+
+    #[test]
+    fn failed_and_skipped_segments_drop_their_partial_translation() {
+        let (mut app, _commands) = app();
+        let failed = complete(&mut app, "一つ");
+        let skipped = complete(&mut app, "二つ");
+        app.on_update(AppUpdate::TranslationPartial(failed, "on".to_string()));
+        app.on_update(AppUpdate::TranslationFailed(failed, "boom".to_string()));
+        app.on_update(AppUpdate::TranslationSkipped(skipped));
+
+        assert_eq!(app.segments[0].status, TranslationStatus::Failed);
+        assert_eq!(app.segments[0].translation, "");
+        assert_eq!(app.segments[1].status, TranslationStatus::Skipped);
+        assert!(app.status.contains("boom"));
+    }
+
+    #[test]
+    fn merged_segments_are_folded_into_the_earlier_one() {
+        let (mut app, _commands) = app();
+        let into = complete(&mut app, "一つ");
+        let from = complete(&mut app, "二つ");
+        app.on_update(AppUpdate::SegmentsMerged { into, from });
+
+        assert_eq!(app.segments.len(), 1);
+        assert_eq!(app.segments[0].id, into);
+        assert_eq!(app.segments[0].source, "一つ 二つ");
+    }
+
+    #[test]
+    fn s_pauses_and_resumes_the_pipeline() {
+        let (mut app, mut commands) = app();
+        app.on_update(AppUpdate::PipelineStateChanged(PipelineState::Listening));
+        app.on_update(AppUpdate::LiveTranscriptUpdate("途中".to_string()));
+
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(app.input_mode, AppInputMode::StoppedTyping);
+        assert_eq!(commands.try_recv(), Ok(PipelineCommand::Pause));
+        app.on_update(AppUpdate::PipelineStateChanged(PipelineState::Paused));
+        assert_eq!(app.pipeline_state, PipelineState::Paused);
+        assert_eq!(app.current_live_transcript, "");
+
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(app.input_mode, AppInputMode::Listening);
+        assert_eq!(commands.try_recv(), Ok(PipelineCommand::Resume));
+    }
+
+    #[test]
+    fn typed_text_is_sent_for_translation_on_enter() {
+        let (mut app, mut commands) = app();
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(commands.try_recv(), Ok(PipelineCommand::Pause));
+        for c in "はい".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+
+        assert_eq!(
+            commands.try_recv(),
+            Ok(PipelineCommand::TranslateText("はい".to_string()))
+        );
+        assert_eq!(app.user_input, "");
+        // Nothing is sent for an empty line.
+        press(&mut app, KeyCode::Enter);
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[test]
+    fn quits_when_finished_only_if_asked_to() {
+        for quit_when_finished in [false, true] {
+            let (app, _commands) = app();
+            let mut app = app.with_quit_when_finished(quit_when_finished);
+            app.on_update(AppUpdate::PipelineStateChanged(PipelineState::Finished));
+            assert_eq!(app.should_quit, quit_when_finished);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Audio capture endpoints: listing them, and picking one by name or index.
+//!
+//! Besides the devices the audio host lists, PulseAudio and PipeWire sources are offered when
+//! `pactl` is available. Their monitor sources carry what a sink plays, so calls and videos
+//! can be translated without a loopback cable.
+
+use anyhow::{Context, anyhow, bail};
+use kalosm::sound::rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use kalosm::sound::rodio::cpal::{self, Device, FromSample, SizedSample};
+use std::process::Command;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::pcm::{PcmDecoder, PcmFormat, PcmStream, SampleFormat};
+
+/// Something audio can be captured from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureEndpoint {
+    /// A device the audio host (ALSA, CoreAudio, WASAPI) lists, by its exact name.
+    Device(String),
+    /// A PulseAudio or PipeWire source, by its exact name, recorded through `parec`.
+    PulseSource(String),
+}
+
+impl CaptureEndpoint {
+    /// The name the endpoint is opened by.
+    pub fn name(&self) -> &str {
+        match self {
+            CaptureEndpoint::Device(name) | CaptureEndpoint::PulseSource(name) => name,
+        }
+    }
+
+    /// Whether this records what a sink plays rather than a microphone.
+    pub fn is_monitor(&self) -> bool {
+        matches!(self, CaptureEndpoint::PulseSource(name) if name.ends_with(".monitor"))
+    }
+}
+
+impl std::fmt::Display for CaptureEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureEndpoint::PulseSource(name) if self.is_monitor() => {
+                write!(f, "System audio ({})", name.trim_end_matches(".monitor"))
+            }
+            endpoint => f.write_str(endpoint.name()),
+        }
+    }
+}
+
+/// A capture endpoint as listed for `--list-devices` and the device picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputDevice {
+    /// Position in the combined list, as accepted by `--device`.
+    pub index: usize,
+    pub endpoint: CaptureEndpoint,
+    /// The device `MicInput::default()` would use.
+    pub is_default: bool,
+}
+
+impl std::fmt::Display for InputDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>2}: {}", self.index, self.endpoint.name())?;
+        if self.endpoint.is_monitor() {
+            f.write_str(" (system audio)")?;
+        }
+        if self.is_default {
+            f.write_str(" (default)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every capture device the default audio host knows about, in the host's order, followed by
+/// the PulseAudio/PipeWire sources.
+pub fn input_devices() -> Result<Vec<InputDevice>, anyhow::Error> {
+    let host = cpal::default_host();
+    let default_name = host
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+    let devices = host
+        .input_devices()
+        .context("Cannot list audio input devices")?;
+    let mut endpoints: Vec<(CaptureEndpoint, bool)> = devices
+        .enumerate()
+        .map(|(index, device)| {
+            let name = device
+                .name()
+                .unwrap_or_else(|_| format!("Unnamed device {index}"));
+            let is_default = default_name.as_ref() == Some(&name);
+            (CaptureEndpoint::Device(name), is_default)
+        })
+        .collect();
+    endpoints.extend(
+        pulse_sources()
+            .into_iter()
+            .map(|name| (CaptureEndpoint::PulseSource(name), false)),
+    );
+    Ok(endpoints
+        .into_iter()
+        .enumerate()
+        .map(|(index, (endpoint, is_default))| InputDevice {
+            index,
+            endpoint,
+            is_default,
+        })
+        .collect())
+}
+
+/// The PulseAudio/PipeWire sources `pactl` lists, monitors included. Empty when there is no
+/// sound server to ask.
+fn pulse_sources() -> Vec<String> {
+    let Ok(output) = Command::new("pactl")
+        .args(["list", "short", "sources"])
+        .output()
+    else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    // Each line is: index, name, driver, sample spec, state, separated by tabs.
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Finds the device `wanted` refers to: an index from [`input_devices`], an exact name, or
+/// failing that, the one device whose name contains it (ignoring case).
+pub fn find_input_device(wanted: &str) -> Result<InputDevice, anyhow::Error> {
+    let devices = input_devices()?;
+    let wanted = wanted.trim();
+    if let Ok(index) = wanted.parse::<usize>() {
+        return devices
+            .into_iter()
+            .find(|device| device.index == index)
+            .ok_or_else(|| anyhow!("There is no audio input device {index} (see --list-devices)"));
+    }
+    if let Some(device) = devices
+        .iter()
+        .find(|device| device.endpoint.name() == wanted)
+    {
+        return Ok(device.clone());
+    }
+    let needle = wanted.to_lowercase();
+    let mut matches: Vec<_> = devices
+        .into_iter()
+        .filter(|device| device.endpoint.name().to_lowercase().contains(&needle))
+        .collect();
+    match matches.len() {
+        0 => bail!("No audio input device matches '{wanted}' (see --list-devices)"),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let names: Vec<String> = matches.iter().map(ToString::to_string).collect();
+            bail!(
+                "'{wanted}' matches several audio input devices:\n{}",
+                names.join("\n")
+            )
+        }
+    }
+}
+
+/// Records the capture device with exactly this name, downmixed and resampled for the pipeline.
+///
+/// kalosm's `MicInput` only opens the default device, so this drives cpal itself.
+pub fn open_device(name: &str) -> Result<PcmStream, anyhow::Error> {
+    let device = device_named(name)?;
+    let config = device
+        .default_input_config()
+        .with_context(|| format!("Cannot open the audio input device '{name}'"))?;
+    let (tx, chunks) = mpsc::channel(64);
+    let (started_tx, started) = std::sync::mpsc::channel();
+    // A cpal stream can't leave the thread that built it, so it lives on this one until
+    // whoever reads the audio drops the PcmStream.
+    std::thread::spawn(move || {
+        let stream = match start_stream(&device, &config, tx.clone()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                started_tx.send(Err(e)).ok();
+                return;
+            }
+        };
+        started_tx.send(Ok(())).ok();
+        while !tx.is_closed() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        drop(stream);
+    });
+    started
+        .recv()
+        .context("The audio capture thread stopped")?
+        .with_context(|| format!("Cannot record from the audio input device '{name}'"))?;
+    Ok(PcmStream::from_channel(chunks))
+}
+
+fn start_stream(
+    device: &Device,
+    config: &cpal::SupportedStreamConfig,
+    tx: mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream, anyhow::Error> {
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::I16 => build_stream::<i16>(device, config, tx)?,
+        cpal::SampleFormat::U16 => build_stream::<u16>(device, config, tx)?,
+        cpal::SampleFormat::I32 => build_stream::<i32>(device, config, tx)?,
+        cpal::SampleFormat::F32 => build_stream::<f32>(device, config, tx)?,
+        sample_format => bail!("unsupported sample format {sample_format}"),
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+fn build_stream<S: SizedSample>(
+    device: &Device,
+    config: &cpal::SupportedStreamConfig,
+    tx: mpsc::Sender<Vec<f32>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    f32: FromSample<S>,
+{
+    let mut decoder = PcmDecoder::new(PcmFormat {
+        sample_format: SampleFormat::F32Le,
+        sample_rate: config.sample_rate().0,
+        channels: config.channels(),
+    });
+    device.build_input_stream(
+        &config.config(),
+        move |data: &[S], _: &_| {
+            let bytes: Vec<u8> = data
+                .iter()
+                .flat_map(|sample| sample.to_sample::<f32>().to_le_bytes())
+                .collect();
+            let samples = decoder.decode(&bytes);
+            if !samples.is_empty() {
+                // Dropped rather than block the audio callback if the pipeline falls behind.
+                tx.try_send(samples).ok();
+            }
+        },
+        // The stream just goes quiet if the device goes away, as the default microphone does.
+        |_| {},
+        None,
+    )
+}
+
+fn device_named(name: &str) -> Result<Device, anyhow::Error> {
+    cpal::default_host()
+        .input_devices()
+        .context("Cannot list audio input devices")?
+        .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+        .ok_or_else(|| anyhow!("The audio input device '{name}' is not available"))
+}
@@ -0,0 +1,244 @@
+use anyhow::Context;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::language::LanguagePair;
+use crate::segment::{Segment, TranslationStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+}
+
+impl SubtitleFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::WebVtt => "vtt",
+        }
+    }
+}
+
+/// Which side of each segment becomes the caption text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionTrack {
+    Source,
+    Translation,
+}
+
+/// Renders the timed segments as a subtitle file.
+///
+/// Segments without a time (typed input) are skipped, as are translations that never
+/// completed when rendering the translation track.
+pub fn render_subtitles(
+    segments: &[Segment],
+    format: SubtitleFormat,
+    track: CaptionTrack,
+) -> String {
+    let mut cues: Vec<_> = segments
+        .iter()
+        .filter_map(|segment| {
+            let time = segment.time?;
+            let text = match track {
+                CaptionTrack::Source => segment.source.trim(),
+                CaptionTrack::Translation if segment.status == TranslationStatus::Done => {
+                    segment.translation.trim()
+                }
+                CaptionTrack::Translation => return None,
+            };
+            let text = cue_text(text, format);
+            (!text.is_empty()).then_some((time, text))
+        })
+        .collect();
+    cues.sort_by_key(|(time, _)| time.start);
+
+    let mut out = String::new();
+    if format == SubtitleFormat::WebVtt {
+        out.push_str("WEBVTT\n\n");
+        if track == CaptionTrack::Source
+            && segments
+                .iter()
+                .any(|segment| segment.status == TranslationStatus::Untranslated)
+        {
+            out.push_str("NOTE Transcribed only; no translation was done.\n\n");
+        }
+    }
+    for (index, (time, text)) in cues.into_iter().enumerate() {
+        let separator = match format {
+            SubtitleFormat::Srt => ',',
+            SubtitleFormat::WebVtt => '.',
+        };
+        if format == SubtitleFormat::Srt {
+            writeln!(out, "{}", index + 1).unwrap();
+        }
+        writeln!(
+            out,
+            "{} --> {}",
+            format_timestamp(time.start, separator),
+            format_timestamp(time.end, separator)
+        )
+        .unwrap();
+        writeln!(out, "{text}\n").unwrap();
+    }
+    out
+}
+
+/// Puts a segment's text on one line, since a blank line would end the cue early. WebVTT
+/// reads cue text as markup, so `&`, `<` and `>` are escaped there, which also keeps a
+/// literal `-->` out of the cue.
+fn cue_text(text: &str, format: SubtitleFormat) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match format {
+        SubtitleFormat::Srt => line,
+        SubtitleFormat::WebVtt => line
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;"),
+    }
+}
+
+/// Formats `hh:mm:ss` followed by milliseconds, e.g. `00:01:02,345` for SRT.
+fn format_timestamp(time: Duration, millis_separator: char) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis_separator,
+        millis % 1000
+    )
+}
+
+/// Writes SRT and WebVTT files for both the source and translated captions, or only the
+/// source captions for a transcribe-only session.
+///
+/// Files are named `<base>.<language code>.<srt|vtt>`. Returns the paths written.
+pub fn export_subtitles(
+    segments: &[Segment],
+    languages: LanguagePair,
+    transcribe_only: bool,
+    base: &Path,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut tracks = vec![(CaptionTrack::Source, languages.source)];
+    if !transcribe_only {
+        tracks.push((CaptionTrack::Translation, languages.target));
+    }
+    let mut written = Vec::new();
+    for format in [SubtitleFormat::Srt, SubtitleFormat::WebVtt] {
+        for &(track, language) in &tracks {
+            let mut file_name = base.as_os_str().to_owned();
+            file_name.push(format!(".{}.{}", language.code(), format.extension()));
+            let path = PathBuf::from(file_name);
+            std::fs::write(&path, render_subtitles(segments, format, track))
+                .with_context(|| format!("Cannot write {}", path.display()))?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::{SegmentId, TimeSpan};
+
+    fn segment(id: u64, source: &str, translation: &str, start_ms: u64, end_ms: u64) -> Segment {
+        let mut segment = Segment::new(SegmentId(id), source.to_string());
+        segment.translation = translation.to_string();
+        segment.status = TranslationStatus::Done;
+        segment.time = Some(TimeSpan {
+            start: Duration::from_millis(start_ms),
+            end: Duration::from_millis(end_ms),
+        });
+        segment
+    }
+
+    #[test]
+    fn timestamps_carry_into_minutes_and_hours() {
+        assert_eq!(format_timestamp(Duration::ZERO, ','), "00:00:00,000");
+        assert_eq!(
+            format_timestamp(Duration::from_millis(62_345), ','),
+            "00:01:02,345"
+        );
+        assert_eq!(
+            format_timestamp(Duration::from_millis(3_600_000 + 59 * 60_000 + 59_999), '.'),
+            "01:59:59.999"
+        );
+        // Sub-millisecond parts are truncated, not rounded.
+        assert_eq!(
+            format_timestamp(Duration::from_micros(1_999), '.'),
+            "00:00:00.001"
+        );
+    }
+
+    #[test]
+    fn srt_numbers_cues_in_time_order() {
+        let segments = [
+            segment(2, "二", "two", 3_000, 4_500),
+            segment(1, "一", "one", 1_000, 2_000),
+        ];
+        assert_eq!(
+            render_subtitles(&segments, SubtitleFormat::Srt, CaptionTrack::Translation),
+            "1\n00:00:01,000 --> 00:00:02,000\none\n\n\
+             2\n00:00:03,000 --> 00:00:04,500\ntwo\n\n"
+        );
+    }
+
+    #[test]
+    fn webvtt_has_a_header_and_dotted_milliseconds() {
+        let segments = [segment(1, " 一 ", "one", 1_250, 2_000)];
+        assert_eq!(
+            render_subtitles(&segments, SubtitleFormat::WebVtt, CaptionTrack::Source),
+            "WEBVTT\n\n00:00:01.250 --> 00:00:02.000\n一\n\n"
+        );
+    }
+
+    #[test]
+    fn untimed_and_untranslated_segments_are_left_out() {
+        let mut typed = segment(1, "入力", "typed", 0, 0);
+        typed.time = None;
+        let mut failed = segment(2, "失敗", "", 1_000, 2_000);
+        failed.status = TranslationStatus::Failed;
+        let mut pending = segment(3, "途中", "half", 3_000, 4_000);
+        pending.status = TranslationStatus::Pending;
+        let done = segment(4, "完了", "done", 5_000, 6_000);
+        let segments = [typed, failed, pending, done];
+
+        assert_eq!(
+            render_subtitles(&segments, SubtitleFormat::Srt, CaptionTrack::Translation),
+            "1\n00:00:05,000 --> 00:00:06,000\ndone\n\n"
+        );
+        let source = render_subtitles(&segments, SubtitleFormat::Srt, CaptionTrack::Source);
+        assert_eq!(source.matches(" --> ").count(), 3);
+        assert!(!source.contains("入力"));
+    }
+
+    #[test]
+    fn transcribe_only_webvtt_says_so() {
+        let mut segment = segment(1, "一", "", 0, 1_000);
+        segment.status = TranslationStatus::Untranslated;
+        let vtt = render_subtitles(&[segment], SubtitleFormat::WebVtt, CaptionTrack::Source);
+        assert!(vtt.starts_with("WEBVTT\n\nNOTE Transcribed only"));
+    }
+
+    #[test]
+    fn multi_line_translations_become_one_line_cues() {
+        let segments = [
+            segment(1, "一", "first line\n\nsecond --> line\n", 0, 1_000),
+            segment(2, "二", "a < b & c", 1_000, 2_000),
+        ];
+        assert_eq!(
+            render_subtitles(&segments, SubtitleFormat::Srt, CaptionTrack::Translation),
+            "1\n00:00:00,000 --> 00:00:01,000\nfirst line second --> line\n\n\
+             2\n00:00:01,000 --> 00:00:02,000\na < b & c\n\n"
+        );
+        assert_eq!(
+            render_subtitles(&segments, SubtitleFormat::WebVtt, CaptionTrack::Translation),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nfirst line second --&gt; line\n\n\
+             00:00:01.000 --> 00:00:02.000\na &lt; b &amp; c\n\n"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! `transvibe translate`: translating lines of text in order, as a Unix filter, with no audio
+//! involved. Each input line gives one output line, so the two can be pasted side by side.
+
+use anyhow::bail;
+use std::io::{BufRead, Write};
+
+use crate::glossary::Glossary;
+use crate::translator::{ContextConfig, ContextPair, ContextWindow, Translator};
+
+/// How lines are grouped and written.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterConfig {
+    /// Lines sent to the model in one request. A batch that comes back with a different
+    /// number of lines is translated again one line at a time.
+    pub batch: usize,
+    /// Copy blank lines through, so paragraphs stay apart. Otherwise they are dropped.
+    pub keep_blank: bool,
+    /// Write `source<TAB>translation` instead of the translation alone.
+    pub tsv: bool,
+    pub context: ContextConfig,
+}
+
+/// Translates lines as they are pushed, writing each batch out once it is translated.
+pub struct LineFilter<'a, T, W> {
+    translator: &'a T,
+    glossary: Glossary,
+    out: W,
+    config: FilterConfig,
+    context: ContextWindow,
+    /// Lines waiting to be translated, with their line numbers.
+    batch: Vec<(usize, String)>,
+    lines_read: usize,
+    failed: usize,
+}
+
+impl<'a, T: Translator, W: Write> LineFilter<'a, T, W> {
+    /// `glossary` should be the one the translator was loaded with; lines that ignore it are
+    /// reported on stderr.
+    pub fn new(translator: &'a T, glossary: Glossary, out: W, config: FilterConfig) -> Self {
+        Self {
+            translator,
+            glossary,
+            out,
+            config,
+            context: ContextWindow::new(config.context),
+            batch: Vec::new(),
+            lines_read: 0,
+            failed: 0,
+        }
+    }
+
+    /// Translates every line of `input`, in order.
+    pub async fn read(&mut self, input: impl BufRead) -> Result<(), anyhow::Error> {
+        for line in input.lines() {
+            self.push(&line?).await?;
+        }
+        Ok(())
+    }
+
+    /// Takes the next line; its batch is translated and written once it is full.
+    pub async fn push(&mut self, line: &str) -> Result<(), anyhow::Error> {
+        self.lines_read += 1;
+        if line.trim().is_empty() {
+            self.flush().await?;
+            if self.config.keep_blank {
+                writeln!(self.out)?;
+            }
+            return Ok(());
+        }
+        self.batch
+            .push((self.lines_read, line.trim_end().to_string()));
+        if self.batch.len() >= self.config.batch.max(1) {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Translates whatever is left, and fails if any line could not be translated.
+    pub async fn finish(mut self) -> Result<(), anyhow::Error> {
+        self.flush().await?;
+        if self.failed > 0 {
+            bail!("{} line(s) could not be translated", self.failed);
+        }
+        Ok(())
+    }
+
+    /// Translates and writes the lines waiting in the current batch.
+    async fn flush(&mut self) -> Result<(), anyhow::Error> {
+        let batch = std::mem::take(&mut self.batch);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let translations = match self.translate_together(&batch).await {
+            Some(translations) => translations,
+            None => {
+                let mut translations = Vec::with_capacity(batch.len());
+                for (number, line) in &batch {
+                    translations.push(self.translate(*number, line).await);
+                }
+                translations
+            }
+        };
+        for ((number, source), translation) in batch.into_iter().zip(translations) {
+            let translation = single_line(&translation);
+            let violations = self.glossary.violations(&source, &translation);
+            if !translation.is_empty() && !violations.is_empty() {
+                let terms: Vec<String> = violations.iter().map(ToString::to_string).collect();
+                eprintln!(
+                    "Line {}: glossary terms not used: {}",
+                    number,
+                    terms.join(", ")
+                );
+            }
+            if self.config.tsv {
+                writeln!(self.out, "{}\t{}", single_line(&source), translation)?;
+            } else {
+                writeln!(self.out, "{}", translation)?;
+            }
+            if !translation.is_empty() {
+                self.context.push(ContextPair {
+                    source,
+                    translation,
+                });
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Translates a batch of several lines in one request, if it comes back line for line.
+    async fn translate_together(&mut self, batch: &[(usize, String)]) -> Option<Vec<String>> {
+        if batch.len() < 2 {
+            return None;
+        }
+        let lines: Vec<String> = batch.iter().map(|(_, line)| line.clone()).collect();
+        let translations = self
+            .translator
+            .translate_lines(&lines, &self.context.recent())
+            .await
+            .ok()?;
+        let lined_up = translations.len() == batch.len()
+            && translations.iter().all(|line| !line.trim().is_empty());
+        lined_up.then_some(translations)
+    }
+
+    /// Translates one line; a failure is reported on stderr and leaves the line empty.
+    async fn translate(&mut self, number: usize, line: &str) -> String {
+        match self
+            .translator
+            .translate(line, &self.context.recent(), |_| {})
+            .await
+        {
+            Ok(translation) if !translation.text.is_empty() => translation.text,
+            Ok(_) => {
+                eprintln!("Line {}: no translation generated", number);
+                self.failed += 1;
+                String::new()
+            }
+            Err(e) => {
+                eprintln!("Line {}: translation failed: {:#}", number, e);
+                self.failed += 1;
+                String::new()
+            }
+        }
+    }
+}
+
+/// Keeps a field on one line and in one TSV column.
+fn single_line(text: &str) -> String {
+    text.split(['\n', '\r', '\t'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTranslator;
+
+    async fn filter(translator: &MockTranslator, config: FilterConfig, input: &str) -> String {
+        let mut out = Vec::new();
+        let mut filter = LineFilter::new(translator, Glossary::default(), &mut out, config);
+        filter.read(input.as_bytes()).await.unwrap();
+        filter.finish().await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn batches_of(batch: usize) -> FilterConfig {
+        FilterConfig {
+            batch,
+            keep_blank: true,
+            tsv: true,
+            context: ContextConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_batch_is_written_line_for_line_in_order() {
+        let translator =
+            MockTranslator::new().with_batch(["一", "二", "三"], ["one", "two", "three"]);
+        let output = filter(&translator, batches_of(3), "一\n二\n三\n\n四\n").await;
+        assert_eq!(output, "一\tone\n二\ttwo\n三\tthree\n\n四\t[en] 四\n");
+    }
+
+    #[tokio::test]
+    async fn a_batch_that_does_not_line_up_is_translated_one_line_at_a_time() {
+        let translator = MockTranslator::new()
+            .with_entry("一", "one")
+            .with_entry("二", "two")
+            .with_entry("三", "three")
+            .with_batch(["一", "二", "三"], ["one two", "three"]);
+        let output = filter(&translator, batches_of(3), "一\n二\n三\n").await;
+        assert_eq!(output, "一\tone\n二\ttwo\n三\tthree\n");
+    }
+
+    #[tokio::test]
+    async fn batch_and_per_line_translation_agree() {
+        let input = "一\n二\n\n三\n四\n五\n";
+        let translator = MockTranslator::new();
+        let together = filter(&translator, batches_of(4), input).await;
+        let one_by_one = filter(&translator, batches_of(1), input).await;
+        assert_eq!(together, one_by_one);
+        assert_eq!(together.lines().count(), input.lines().count());
+    }
+}
@@ -0,0 +1,192 @@
+//! User-supplied terminology: source terms that must always be translated a particular way.
+//!
+//! The glossary goes into the translation prompt and each translation is checked against it.
+//! It does not reach Whisper, which kalosm gives no initial prompt to bias recognition with.
+//!
+//! Glossaries are read from TSV (`source<TAB>target` per line, `#` comments) or TOML
+//! (`"source" = "target"`, either at the top level or under a `[terms]` table).
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// One required translation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlossaryEntry {
+    pub source: String,
+    pub target: String,
+}
+
+impl std::fmt::Display for GlossaryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} → {}", self.source, self.target)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Glossary {
+    entries: Vec<GlossaryEntry>,
+}
+
+impl Glossary {
+    pub fn new(entries: impl IntoIterator<Item = GlossaryEntry>) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+        }
+    }
+
+    /// Reads a glossary, picking the format from the extension (`.toml`, otherwise TSV).
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read glossary {}", path.display()))?;
+        let is_toml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        let glossary = if is_toml {
+            Self::parse_toml(&text)
+        } else {
+            Self::parse_tsv(&text)
+        };
+        glossary.with_context(|| format!("Invalid glossary {}", path.display()))
+    }
+
+    pub fn parse_tsv(text: &str) -> Result<Self, anyhow::Error> {
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((source, target)) = line.split_once('\t') else {
+                bail!("line {}: expected `source<TAB>target`", index + 1);
+            };
+            entries.push(entry(source, target, || format!("line {}", index + 1))?);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn parse_toml(text: &str) -> Result<Self, anyhow::Error> {
+        let table: toml::Table = text.parse()?;
+        let terms = match table.get("terms") {
+            Some(toml::Value::Table(terms)) => terms,
+            _ => &table,
+        };
+        let mut entries = Vec::new();
+        for (source, target) in terms {
+            let Some(target) = target.as_str() else {
+                bail!("`{source}`: the target term must be a string");
+            };
+            entries.push(entry(source, target, || format!("`{source}`"))?);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[GlossaryEntry] {
+        &self.entries
+    }
+
+    /// The entries whose source term occurs in `text`.
+    pub fn matching<'a>(&'a self, text: &str) -> impl Iterator<Item = &'a GlossaryEntry> {
+        let text = text.to_lowercase();
+        self.entries
+            .iter()
+            .filter(move |entry| text.contains(&entry.source.to_lowercase()))
+    }
+
+    /// Entries used in `source` whose required term is missing from `translation`.
+    pub fn violations(&self, source: &str, translation: &str) -> Vec<GlossaryEntry> {
+        let translation = translation.to_lowercase();
+        self.matching(source)
+            .filter(|entry| !translation.contains(&entry.target.to_lowercase()))
+            .cloned()
+            .collect()
+    }
+}
+
+fn entry(
+    source: &str,
+    target: &str,
+    location: impl FnOnce() -> String,
+) -> Result<GlossaryEntry, anyhow::Error> {
+    let (source, target) = (source.trim(), target.trim());
+    if source.is_empty() || target.is_empty() {
+        bail!("{}: empty term", location());
+    }
+    Ok(GlossaryEntry {
+        source: source.to_string(),
+        target: target.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glossary(pairs: &[(&str, &str)]) -> Glossary {
+        Glossary::new(pairs.iter().map(|(source, target)| GlossaryEntry {
+            source: source.to_string(),
+            target: target.to_string(),
+        }))
+    }
+
+    #[test]
+    fn tsv_skips_comments_and_blank_lines() {
+        let parsed = Glossary::parse_tsv(
+            "# product names\n\nトランスバイブ\tTransVibe\n  会議室 \t meeting room \n",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            glossary(&[("トランスバイブ", "TransVibe"), ("会議室", "meeting room")])
+        );
+    }
+
+    #[test]
+    fn tsv_errors_name_the_line() {
+        let error = Glossary::parse_tsv("a\tb\nno tab here\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: expected `source<TAB>target`");
+    }
+
+    #[test]
+    fn toml_terms_can_be_top_level_or_in_a_table() {
+        let expected = glossary(&[("会議室", "meeting room")]);
+        assert_eq!(
+            Glossary::parse_toml("\"会議室\" = \"meeting room\"").unwrap(),
+            expected
+        );
+        assert_eq!(
+            Glossary::parse_toml("[terms]\n\"会議室\" = \"meeting room\"").unwrap(),
+            expected
+        );
+        assert!(Glossary::parse_toml("\"会議室\" = 3").is_err());
+        let error = Glossary::parse_toml("\"\" = \"meeting room\"").unwrap_err();
+        assert_eq!(error.to_string(), "``: empty term");
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        let glossary = glossary(&[("Kalosm", "Kalosm"), ("会議", "meeting")]);
+        let matched: Vec<&str> = glossary
+            .matching("kalosm の使い方")
+            .map(|entry| entry.source.as_str())
+            .collect();
+        assert_eq!(matched, ["Kalosm"]);
+    }
+
+    #[test]
+    fn violations_are_terms_in_the_source_missing_from_the_translation() {
+        let glossary = glossary(&[
+            ("トランスバイブ", "TransVibe"),
+            ("会議", "meeting"),
+            ("議事録", "minutes"),
+        ]);
+        let violations = glossary.violations("トランスバイブの会議", "The transvibe conference");
+        // "transvibe" counts despite its case; "minutes" wasn't needed.
+        assert_eq!(violations, glossary.entries()[1..2]);
+        assert!(glossary.violations("雑談", "small talk").is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+//! Running without the TUI, for scripts, SSH sessions and services: each finished segment is
+//! printed to stdout as one line, and errors and model loading progress go to stderr.
+
+use anyhow::bail;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::app::{App, AppUpdate, describe_load, format_clock};
+use crate::loading::{LoadProgress, LoadStage};
+use crate::pipeline::PipelineState;
+use crate::segment::{Segment, SegmentId, TranslationStatus};
+use crate::session::SessionSegment;
+
+/// How lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// `[0:12] source → translation`, for reading.
+    #[default]
+    Text,
+    /// One JSON object per line, for other programs.
+    JsonLines,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Text => "text",
+            OutputFormat::JsonLines => "jsonl",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(OutputFormat::Text),
+            "jsonl" | "json" | "ndjson" => Ok(OutputFormat::JsonLines),
+            _ => Err(format!(
+                "unknown output format '{s}' (expected text or jsonl)"
+            )),
+        }
+    }
+}
+
+/// One line of JSON Lines output.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    /// A finished segment, in the same shape as in saved sessions.
+    Segment(SessionSegment),
+    /// The live transcript of the speech being transcribed.
+    Partial { text: &'a str },
+    /// The translation of a segment generated so far.
+    TranslationPartial { id: u64, text: &'a str },
+}
+
+/// Writes the lines for the updates it is shown.
+pub struct HeadlessOutput<W> {
+    out: W,
+    format: OutputFormat,
+    /// Also print the live transcript and translations as they are generated.
+    partials: bool,
+    /// How far each model had got when its progress was last printed.
+    load_steps: HashMap<String, String>,
+}
+
+impl<W: Write> HeadlessOutput<W> {
+    pub fn new(out: W, format: OutputFormat, partials: bool) -> Self {
+        Self {
+            out,
+            format,
+            partials,
+            load_steps: HashMap::new(),
+        }
+    }
+
+    /// Applies `update` to `app`, printing whatever it finishes.
+    pub fn apply(&mut self, app: &mut App, update: AppUpdate) -> std::io::Result<()> {
+        let finished = match &update {
+            AppUpdate::SegmentComplete(segment)
+                if segment.status == TranslationStatus::Untranslated =>
+            {
+                Some(segment.id)
+            }
+            AppUpdate::Translation(id, _) | AppUpdate::TranslationSkipped(id) => Some(*id),
+            AppUpdate::TranslationFailed(id, error) => {
+                eprintln!("Translation of segment {} failed: {}", id, error);
+                Some(*id)
+            }
+            AppUpdate::LiveTranscriptUpdate(text) if self.partials && !text.is_empty() => {
+                self.partial(None, text)?;
+                None
+            }
+            AppUpdate::TranslationPartial(id, text) if self.partials => {
+                self.partial(Some(*id), text)?;
+                None
+            }
+            AppUpdate::Error(error) => {
+                eprintln!("ERROR: {}", error);
+                None
+            }
+            AppUpdate::ModelLoadProgress(progress) => {
+                self.load_progress(progress);
+                None
+            }
+            _ => None,
+        };
+        app.on_update(update);
+        if let Some(segment) = finished.and_then(|id| app.segment_mut(id)) {
+            self.segment(segment)?;
+        }
+        Ok(())
+    }
+
+    fn segment(&mut self, segment: &Segment) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Text => {
+                if let Some(time) = segment.time {
+                    write!(self.out, "[{}] ", format_clock(time.start))?;
+                }
+                match segment.status {
+                    TranslationStatus::Untranslated => writeln!(self.out, "{}", segment.source)?,
+                    _ => writeln!(
+                        self.out,
+                        "{} → {}",
+                        segment.source,
+                        segment.translation_display()
+                    )?,
+                }
+            }
+            OutputFormat::JsonLines => {
+                self.json(&JsonLine::Segment(SessionSegment::from(segment)))?;
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Prints a model's progress every tenth of a download and quarter of a load, rather than
+    /// on every callback.
+    fn load_progress(&mut self, progress: &LoadProgress) {
+        let step = match &progress.stage {
+            LoadStage::Waiting => "waiting".to_string(),
+            LoadStage::Downloading { file, .. } => {
+                format!("{} {}", file, (progress.ratio() * 10.0) as u32)
+            }
+            LoadStage::Loading(_) => format!("loading {}", (progress.ratio() * 4.0) as u32),
+            LoadStage::Ready => "ready".to_string(),
+        };
+        if self.load_steps.get(&progress.model) != Some(&step) {
+            eprintln!("{}", describe_load(progress));
+            self.load_steps.insert(progress.model.clone(), step);
+        }
+    }
+
+    fn partial(&mut self, id: Option<SegmentId>, text: &str) -> std::io::Result<()> {
+        match (self.format, id) {
+            (OutputFormat::Text, None) => writeln!(self.out, "… {}", text)?,
+            (OutputFormat::Text, Some(id)) => writeln!(self.out, "… {} {}", id, text)?,
+            (OutputFormat::JsonLines, None) => self.json(&JsonLine::Partial { text })?,
+            (OutputFormat::JsonLines, Some(id)) => {
+                self.json(&JsonLine::TranslationPartial { id: id.0, text })?
+            }
+        }
+        self.out.flush()
+    }
+
+    fn json(&mut self, line: &JsonLine) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.out, line)?;
+        writeln!(self.out)
+    }
+}
+
+/// How long a run that was asked to stop waits for the segments it still has to finish.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Feeds pipeline updates to `app` until it quits, the pipeline goes away, or the process is
+/// asked to stop (Ctrl+C, or SIGTERM from a service manager). Fails if the pipeline did, so
+/// scripts see a non-zero exit status.
+pub async fn run(
+    app: &mut App,
+    output: &mut HeadlessOutput<impl Write>,
+) -> Result<(), anyhow::Error> {
+    run_until(app, output, shutdown_signal()).await
+}
+
+/// [`run`], asked to stop when `stop` completes instead of on a signal. The pipeline is then
+/// told to stop capturing, and what it already has is transcribed, translated and printed,
+/// for up to [`STOP_TIMEOUT`] or until a second signal. Segments still waiting after that
+/// are printed untranslated, with a note on stderr.
+pub async fn run_until(
+    app: &mut App,
+    output: &mut HeadlessOutput<impl Write>,
+    stop: impl Future<Output = ()>,
+) -> Result<(), anyhow::Error> {
+    tokio::pin!(stop);
+    let mut stopping = false;
+    while !app.should_quit && app.pipeline_error.is_none() {
+        tokio::select! {
+            update = app.next_update() => match update {
+                Some(update) => output.apply(app, update)?,
+                None => break,
+            },
+            _ = &mut stop => {
+                stopping = true;
+                break;
+            }
+        }
+    }
+    if stopping {
+        app.stop_pipeline();
+        let finished = async {
+            while app.pipeline_state != PipelineState::Finished && app.pipeline_error.is_none() {
+                match app.next_update().await {
+                    Some(update) => output.apply(app, update)?,
+                    None => break,
+                }
+            }
+            std::io::Result::Ok(())
+        };
+        tokio::select! {
+            result = tokio::time::timeout(STOP_TIMEOUT, finished) => {
+                if let Ok(result) = result {
+                    result?;
+                }
+            }
+            _ = shutdown_signal() => {}
+        }
+        let waiting: Vec<_> = app
+            .segments
+            .iter()
+            .filter(|segment| segment.status == TranslationStatus::Pending)
+            .collect();
+        if !waiting.is_empty() {
+            eprintln!(
+                "Stopped before translating {} segment(s); they were dropped:",
+                waiting.len()
+            );
+            for segment in waiting {
+                let mut untranslated = segment.clone();
+                untranslated.translation.clear();
+                untranslated.status = TranslationStatus::Untranslated;
+                output.segment(&untranslated)?;
+            }
+        }
+    }
+    if let Some(error) = &app.pipeline_error {
+        bail!("{}", error);
+    }
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut terminate) =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        return;
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
@@ -0,0 +1,74 @@
+use anyhow::Context;
+use kalosm::sound::rodio::{Decoder, Source};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::devices::CaptureEndpoint;
+use crate::pcm::PcmFormat;
+use crate::udp::UdpConfig;
+
+/// Where the pipeline gets its audio from.
+#[derive(Debug, Clone)]
+pub enum AudioInput {
+    /// A microphone, a system audio monitor or another capture endpoint; `None` for the
+    /// system's default microphone.
+    Capture { endpoint: Option<CaptureEndpoint> },
+    /// A recording on disk, in any format rodio can decode (WAV, FLAC, MP3, OGG Vorbis).
+    File(PathBuf),
+    /// Raw PCM piped in on stdin, e.g. from ffmpeg, arecord or sox.
+    Stdin(PcmFormat),
+    /// Raw PCM or RTP packets sent to a local UDP port, e.g. from a mixer on another machine.
+    Udp { addr: SocketAddr, config: UdpConfig },
+}
+
+impl Default for AudioInput {
+    fn default() -> Self {
+        AudioInput::Capture { endpoint: None }
+    }
+}
+
+impl AudioInput {
+    /// Short label for the status bar.
+    pub fn label(&self) -> String {
+        match self {
+            AudioInput::Capture { endpoint: None } => "Microphone".to_string(),
+            AudioInput::Capture {
+                endpoint: Some(endpoint),
+            } => endpoint.to_string(),
+            AudioInput::File(path) => path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            AudioInput::Stdin(format) => format!(
+                "stdin ({}, {} Hz, {} ch)",
+                format.sample_format, format.sample_rate, format.channels
+            ),
+            AudioInput::Udp { addr, config } => {
+                format!("{} {}", if config.rtp { "RTP" } else { "UDP" }, addr)
+            }
+        }
+    }
+}
+
+/// A decoded audio file, ready to stream into the pipeline.
+pub struct AudioFile {
+    pub decoder: Decoder<BufReader<File>>,
+    /// Length of the recording, if the container reports it.
+    pub total_duration: Option<Duration>,
+}
+
+/// Opens and probes an audio file, so unreadable or unsupported files fail before any model loads.
+pub fn open_audio_file(path: &Path) -> Result<AudioFile, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let decoder = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Cannot decode {} as WAV, FLAC, MP3 or OGG", path.display()))?;
+    let total_duration = decoder.total_duration();
+    Ok(AudioFile {
+        decoder,
+        total_duration,
+    })
+}
@@ -0,0 +1,99 @@
+use kalosm::sound::WhisperLanguage;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A spoken or written language, identified by its ISO 639-1 code (`ja`, `en`, `ko`, ...).
+///
+/// Any language Whisper can recognize is accepted, for either side of the pair.
+#[derive(Debug, Clone, Copy)]
+pub struct Language(WhisperLanguage);
+
+impl Language {
+    pub const JAPANESE: Language = Language(WhisperLanguage::Japanese);
+    pub const ENGLISH: Language = Language(WhisperLanguage::English);
+
+    /// The ISO 639-1 code, e.g. `ja`.
+    pub fn code(&self) -> String {
+        self.0.to_string()
+    }
+
+    /// The English name, e.g. `Japanese`, as used in prompts and panel titles.
+    pub fn name(&self) -> String {
+        // The variant names are the English names, written in CamelCase ("HaitianCreole").
+        let variant = format!("{:?}", self.0);
+        let mut name = String::with_capacity(variant.len() + 2);
+        for (i, c) in variant.chars().enumerate() {
+            if i > 0 && c.is_uppercase() {
+                name.push(' ');
+            }
+            name.push(c);
+        }
+        name
+    }
+
+    pub fn whisper_language(&self) -> WhisperLanguage {
+        self.0
+    }
+}
+
+impl PartialEq for Language {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for Language {}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WhisperLanguage::from_str(&s.trim().to_ascii_lowercase())
+            .map(Language)
+            .map_err(|_| {
+                format!("unknown language code '{s}' (expected an ISO 639-1 code such as ja, en, ko, zh or es)")
+            })
+    }
+}
+
+impl Serialize for Language {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The language being spoken and the language to translate it into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanguagePair {
+    pub source: Language,
+    pub target: Language,
+}
+
+impl Default for LanguagePair {
+    fn default() -> Self {
+        Self {
+            source: Language::JAPANESE,
+            target: Language::ENGLISH,
+        }
+    }
+}
+
+impl fmt::Display for LanguagePair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}→{}", self.source.code(), self.target.code())
+    }
+}
@@ -0,0 +1,36 @@
+//! Transcribe+Translate: real-time, local transcription and translation.
+//!
+//! The pipeline turns speech chunks into text with a [`Transcriber`] and hands each
+//! finished segment to a [`Translator`]. Whisper and Qwen back the defaults; the
+//! [`mock`] module has deterministic stand-ins for running without models, and
+//! [`source::SyntheticSource`] generates audio for running without a microphone.
+
+pub mod app;
+pub mod devices;
+pub mod export;
+pub mod filter;
+pub mod glossary;
+pub mod headless;
+pub mod input;
+pub mod language;
+pub mod loading;
+pub mod mock;
+pub mod models;
+pub mod pcm;
+pub mod pipeline;
+pub mod queue;
+pub mod segment;
+pub mod session;
+pub mod source;
+pub mod transcriber;
+pub mod translator;
+pub mod udp;
+
+pub use app::{App, AppInputMode, AppUpdate};
+pub use input::AudioInput;
+pub use language::{Language, LanguagePair};
+pub use pipeline::{PipelineCommand, PipelineState};
+pub use segment::{Segment, SegmentId, TimeSpan, TranslationStatus};
+pub use session::{Session, SessionModels};
+pub use transcriber::{Transcriber, WhisperTranscriber};
+pub use translator::{LlamaTranslator, Translation, Translator};
@@ -0,0 +1,109 @@
+//! Progress reports while the models download and load, for the gauges shown at startup.
+
+use kalosm::language::ModelLoadingProgress;
+use std::time::{Duration, Instant};
+
+/// Where one model is in getting ready.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadStage {
+    /// Not started yet.
+    Waiting,
+    /// Fetching `file`. `downloaded` counts bytes already cached, too.
+    Downloading {
+        file: String,
+        downloaded: u64,
+        total: u64,
+        /// Unknown until enough of the file has arrived to measure the rate.
+        eta: Option<Duration>,
+    },
+    /// Reading the downloaded weights into memory, from 0.0 to 1.0.
+    Loading(f32),
+    Ready,
+}
+
+/// A progress report for one model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadProgress {
+    /// The model's name as recorded in sessions, e.g. `whisper small`.
+    pub model: String,
+    pub stage: LoadStage,
+}
+
+impl LoadProgress {
+    /// How far along the current stage is, from 0.0 to 1.0.
+    pub fn ratio(&self) -> f64 {
+        match self.stage {
+            LoadStage::Downloading {
+                downloaded, total, ..
+            } if total > 0 => (downloaded as f64 / total as f64).clamp(0.0, 1.0),
+            LoadStage::Waiting | LoadStage::Downloading { .. } => 0.0,
+            LoadStage::Loading(fraction) => f64::from(fraction).clamp(0.0, 1.0),
+            LoadStage::Ready => 1.0,
+        }
+    }
+}
+
+/// Turns kalosm's loading callbacks for one model into [`LoadProgress`] reports,
+/// estimating how long each download has left from the rate seen so far.
+pub struct LoadTracker {
+    model: String,
+    /// The file being downloaded, when we first heard of it, and how much was cached by then.
+    download: Option<(String, Instant, u64)>,
+}
+
+impl LoadTracker {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            download: None,
+        }
+    }
+
+    pub fn update(&mut self, progress: ModelLoadingProgress) -> LoadProgress {
+        let stage = match progress {
+            ModelLoadingProgress::Downloading { source, progress } => {
+                let (downloaded, total) = (progress.progress, progress.size);
+                let (_, started, initial) = match &mut self.download {
+                    Some(download) if download.0 == source => download,
+                    download => download.insert((source.clone(), Instant::now(), downloaded)),
+                };
+                let fetched = downloaded.saturating_sub(*initial);
+                let elapsed = started.elapsed();
+                // A second's worth of rate is enough to stop the estimate jumping around.
+                let eta = (fetched > 0 && elapsed >= Duration::from_secs(1)).then(|| {
+                    elapsed.mul_f64(total.saturating_sub(downloaded) as f64 / fetched as f64)
+                });
+                LoadStage::Downloading {
+                    file: source,
+                    downloaded,
+                    total,
+                    eta,
+                }
+            }
+            ModelLoadingProgress::Loading { progress } => {
+                self.download = None;
+                LoadStage::Loading(progress)
+            }
+        };
+        LoadProgress {
+            model: self.model.clone(),
+            stage,
+        }
+    }
+
+    /// The report for a model that hasn't started loading.
+    pub fn waiting(&self) -> LoadProgress {
+        LoadProgress {
+            model: self.model.clone(),
+            stage: LoadStage::Waiting,
+        }
+    }
+
+    /// The report for a model that finished loading.
+    pub fn ready(&self) -> LoadProgress {
+        LoadProgress {
+            model: self.model.clone(),
+            stage: LoadStage::Ready,
+        }
+    }
+}
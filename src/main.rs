@@ -1,706 +1,510 @@
+use anyhow::Context;
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use color_eyre::Result;
-use crossterm::event::KeyModifiers;
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
-use futures_util::StreamExt;
-use kalosm::language::*;
-use kalosm::sound::*;
-use ratatui::widgets::{Scrollbar, ScrollbarOrientation, ScrollbarState};
-use ratatui::{
-    prelude::*,
-    widgets::{Block, Borders, Paragraph, Wrap},
-};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use color_eyre::eyre::eyre;
+use ratatui::prelude::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
 use tokio::sync::mpsc;
-
-#[derive(Debug)]
-enum AppUpdate {
-    LiveJapaneseUpdate(String),
-    JapaneseSegmentComplete(String),
-    EnglishTranslation(String),
-    SamplesProcessed(usize),
-    RawSamplesDetected(usize),
-    StatusUpdate(String),
-    Error(String),
+use transvibe::devices::{find_input_device, input_devices};
+use transvibe::export::export_subtitles;
+use transvibe::filter::{FilterConfig, LineFilter};
+use transvibe::glossary::Glossary;
+use transvibe::headless::{self, HeadlessOutput, OutputFormat};
+use transvibe::input::open_audio_file;
+use transvibe::models::{LlmSettings, ModelSettings};
+use transvibe::pcm::{PIPELINE_SAMPLE_RATE, PcmFormat, SampleFormat};
+use transvibe::pipeline::{PipelineConfig, TranslationConfig, audio_processing_task};
+use transvibe::queue::{BacklogPolicy, QueueConfig};
+use transvibe::translator::{ContextConfig, LlamaTranslator};
+use transvibe::udp::UdpConfig;
+use transvibe::{App, AppUpdate, AudioInput, Language, LanguagePair, Session};
+
+/// Real-time, local transcription and translation.
+#[derive(Parser, Debug)]
+// Options before a subcommand are refused, since most are for transcribing audio. The global
+// ones (languages, translation model, glossary and context) go after it instead.
+#[command(version, about, args_conflicts_with_subcommands = true)]
+#[command(group(ArgGroup::new("pcm_input").args(["stdin_pcm", "udp"])))]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Transcribe an audio file (WAV, FLAC, MP3 or OGG) instead of the microphone.
+    #[arg(short, long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// Read raw PCM audio from stdin, e.g. `ffmpeg -i URL -f f32le -ar 16000 - | transvibe
+    /// --stdin-pcm`. Exits once the input ends and the last segment is translated.
+    #[arg(long, conflicts_with_all = ["input", "device"])]
+    stdin_pcm: bool,
+
+    /// Listen for audio on this UDP port (or ADDRESS:PORT): raw PCM datagrams, or RTP
+    /// packets with --rtp.
+    #[arg(long, value_name = "PORT|ADDR", value_parser = parse_udp_addr)]
+    #[arg(conflicts_with_all = ["input", "device"])]
+    udp: Option<SocketAddr>,
+
+    /// The --udp datagrams are RTP packets with L16 payloads, which are reordered and have
+    /// lost packets replaced with silence.
+    #[arg(long, requires = "udp")]
+    rtp: bool,
+
+    /// How many RTP packets may arrive after a missing one before it counts as lost.
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "rtp")]
+    jitter_packets: usize,
+
+    /// Sample format of --stdin-pcm or --udp: f32le, s16le, s16be or s32le. RTP L16 is
+    /// always s16be.
+    #[arg(long, value_name = "FORMAT", default_value_t = SampleFormat::default())]
+    #[arg(requires = "pcm_input", conflicts_with = "rtp")]
+    pcm_format: SampleFormat,
+
+    /// Sample rate of --stdin-pcm or --udp in Hz; anything but 16000 is resampled.
+    #[arg(long, value_name = "HZ", default_value_t = PIPELINE_SAMPLE_RATE, requires = "pcm_input")]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    pcm_rate: u32,
+
+    /// Interleaved channels in --stdin-pcm or --udp; they are mixed down to mono.
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "pcm_input")]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    pcm_channels: u16,
+
+    /// Capture from this input device or PulseAudio/PipeWire source, by its number or (part
+    /// of) its name from --list-devices. Pick a `.monitor` source to translate system audio.
+    /// Ctrl+D switches devices while running.
+    #[arg(long, value_name = "NAME|INDEX", conflicts_with = "input")]
+    device: Option<String>,
+
+    /// Print the audio input devices and sources, monitors included, and exit.
+    #[arg(long)]
+    list_devices: bool,
+
+    /// Run without the TUI: print each finished segment and its translation to stdout, and
+    /// errors to stderr. Stops at the end of a file or stdin, or on Ctrl+C/SIGTERM once the
+    /// speech already heard is translated.
+    #[arg(long)]
+    headless: bool,
+
+    /// --headless output: text (one readable line per segment) or jsonl (one JSON object per
+    /// line).
+    #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::default(), requires = "headless")]
+    output: OutputFormat,
+
+    /// With --headless, also print the live transcript and translations as they are generated.
+    #[arg(long, requires = "headless")]
+    partials: bool,
+
+    /// Language being spoken, as an ISO 639-1 code [default: ja, or the resumed session's,
+    /// which it must match].
+    #[arg(long, value_name = "CODE", global = true)]
+    source_lang: Option<Language>,
+
+    /// Language to translate into, as an ISO 639-1 code [default: en, or the resumed
+    /// session's, which it must match].
+    #[arg(long, value_name = "CODE", global = true)]
+    target_lang: Option<Language>,
+
+    /// Write SRT and WebVTT subtitles to BASE.<lang>.srt/.vtt when processing finishes and on exit.
+    /// Without it, Ctrl+E exports next to the input file (or to ./transvibe-<timestamp>).
+    #[arg(long, value_name = "BASE")]
+    export: Option<PathBuf>,
+
+    /// TOML file with model settings (`whisper = "small"`, and `preset` or `model` +
+    /// `tokenizer` under `[llm]`). Flags override it.
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+
+    /// Whisper model size, e.g. tiny, base, small, medium, large-v2 or quantized-large-v3-turbo
+    /// [default: quantized-large-v3-turbo].
+    #[arg(long, value_name = "NAME")]
+    whisper_model: Option<String>,
+
+    /// Built-in translation model, e.g. qwen-2.5-3b-instruct or llama-3.2-3b-chat
+    /// [default: qwen-2.5-7b-instruct].
+    #[arg(long, value_name = "NAME", conflicts_with = "llm_gguf", global = true)]
+    llm: Option<String>,
+
+    /// Translate with a local GGUF model instead of a built-in one. Needs --llm-tokenizer.
+    #[arg(long, value_name = "FILE", global = true)]
+    llm_gguf: Option<PathBuf>,
+
+    /// tokenizer.json for --llm-gguf.
+    #[arg(long, value_name = "FILE", global = true)]
+    llm_tokenizer: Option<PathBuf>,
+
+    /// Load the models from this directory and never download anything. It is laid out like
+    /// kalosm's cache (<repo>/<revision>/<file>), so a copy of that cache works as is.
+    #[arg(long, value_name = "DIR", global = true)]
+    model_dir: Option<PathBuf>,
+
+    /// Only transcribe: don't load a translation model, and show, save and export just the
+    /// transcript.
+    #[arg(long, conflicts_with_all = ["llm", "llm_gguf", "llm_tokenizer", "glossary"])]
+    transcribe_only: bool,
+
+    /// Required translations for names and jargon, as TSV (source<TAB>target) or TOML
+    /// ("source" = "target"). Translations that don't use them are flagged.
+    #[arg(long, value_name = "FILE", global = true)]
+    glossary: Option<PathBuf>,
+
+    /// How many previous segments to send along as context when translating (0 for none).
+    #[arg(long, value_name = "N", default_value_t = ContextConfig::default().max_pairs, global = true)]
+    context_segments: usize,
+
+    /// Rough token budget for that context; the oldest segments are left out first.
+    #[arg(long, value_name = "TOKENS", default_value_t = ContextConfig::default().token_budget, global = true)]
+    context_tokens: usize,
+
+    /// How many segments may wait for translation before --backlog kicks in.
+    #[arg(long, value_name = "N", default_value_t = QueueConfig::default().capacity)]
+    queue_size: usize,
+
+    /// What to do with new speech when translation falls that far behind:
+    /// wait, drop (the oldest waiting segment) or merge (into the last waiting segment).
+    /// Files always wait.
+    #[arg(long, value_name = "POLICY", default_value_t = BacklogPolicy::default())]
+    backlog: BacklogPolicy,
+
+    /// Reopen a saved session to scroll through it and keep appending to it.
+    /// New segments are saved back to the same file unless --save is given.
+    #[arg(long, value_name = "SESSION")]
+    resume: Option<PathBuf>,
+
+    /// Where to save the session on Ctrl+S and on exit [default: BASE.session.json].
+    #[arg(long, value_name = "SESSION")]
+    save: Option<PathBuf>,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum AppInputMode {
-    Listening,
-    StoppedTyping,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Translate text line by line, from files or stdin to stdout, with no audio involved.
+    /// Each input line gives one output line, in order.
+    Translate(TranslateArgs),
 }
 
-struct App {
-    status: String,
-    current_live_japanese: String,
-    completed_japanese: Vec<String>,
-    completed_translations: Vec<String>,
-    rx: mpsc::Receiver<AppUpdate>,
-    should_quit: bool,
-    input_mode: AppInputMode,
-    user_input: String, // For when typing is enabled
-    // Shared state to control the audio processing task
-    is_listening_shared: Arc<AtomicBool>,
-    japanese_scroll_state: ScrollbarState,
-    japanese_scroll: usize,
-    english_scroll_state: ScrollbarState,
-    english_scroll: u16,
-    total_samples_listened: usize,
-    raw_samples_count: usize,
+#[derive(Args, Debug)]
+struct TranslateArgs {
+    /// Text files to translate one after another; none (or `-`) reads stdin.
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Send this many lines to the model at a time. Larger batches give it more to go on and
+    /// need fewer requests.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    batch: u32,
+
+    /// Copy blank lines to the output instead of dropping them, so paragraphs stay apart.
+    #[arg(long)]
+    keep_blank: bool,
+
+    /// Write source<TAB>translation on each line instead of the translation alone.
+    #[arg(long)]
+    tsv: bool,
 }
 
-impl App {
-    fn new(rx: mpsc::Receiver<AppUpdate>) -> Self {
-        Self {
-            status: "Initializing... Press 's' to Stop/Start, 'q' to Quit".to_string(),
-            current_live_japanese: String::new(),
-            completed_japanese: Vec::new(),
-            completed_translations: Vec::new(),
-            rx,
-            should_quit: false,
-            input_mode: AppInputMode::Listening,
-            user_input: String::new(),
-            is_listening_shared: Arc::new(AtomicBool::new(true)), // Start in listening mode
-            japanese_scroll_state: ScrollbarState::default(),
-            japanese_scroll: 0,
-            english_scroll_state: ScrollbarState::default(),
-            english_scroll: 0,
-            total_samples_listened: 0,
-            raw_samples_count: 0,
-        }
+/// A bare port listens on every interface.
+fn parse_udp_addr(s: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = s.parse::<u16>() {
+        return Ok(SocketAddr::from(([0, 0, 0, 0], port)));
     }
+    s.parse()
+        .map_err(|_| format!("'{s}' is neither a port nor an ADDRESS:PORT"))
+}
 
-    fn on_update(&mut self, update: AppUpdate) {
-        match update {
-            AppUpdate::StatusUpdate(s) => self.status = s,
-            AppUpdate::LiveJapaneseUpdate(s) => self.current_live_japanese = s,
-            AppUpdate::JapaneseSegmentComplete(jp_text) => {
-                self.completed_japanese.insert(0, jp_text);
-                self.current_live_japanese.clear();
-                // Always insert a placeholder for the new Japanese text at the beginning
-                self.completed_translations
-                    .insert(0, "Translating...".to_string());
-
-                // Defensive: Ensure translation list doesn't grow longer than Japanese list.
-                while self.completed_translations.len() > self.completed_japanese.len() {
-                    self.completed_translations.pop(); // Remove from the end (oldest assumed extras)
-                }
-            }
-            AppUpdate::EnglishTranslation(en_text) => {
-                let jp_len = self.completed_japanese.len();
-                let tr_len = self.completed_translations.len();
-
-                // Try to update the placeholder at the beginning (index 0), as it's the newest.
-                if tr_len > 0 && self.completed_translations[0] == "Translating..." {
-                    self.completed_translations[0] = en_text;
-                }
-                // Fallback: find the earliest "Translating..." placeholder and update it.
-                // This covers cases where translations might arrive out of order for older segments.
-                else if let Some(index) = self
-                    .completed_translations
-                    .iter()
-                    .position(|t| t == "Translating...")
-                {
-                    self.completed_translations[index] = en_text;
-                }
-                // Further fallback: if no placeholder is found and lengths allow, insert new translation at the top.
-                // This case should be rare if JapaneseSegmentComplete always adds a placeholder.
-                else if tr_len < jp_len {
-                    self.completed_translations.insert(0, en_text);
-                }
-                // If none of the above (e.g. tr_len >= jp_len and no placeholder found),
-                // the translation might be an anomaly or for an already translated segment.
-                // We'll let the cleanup logic below adjust list lengths if necessary.
-
-                // Defensive: Ensure translation list doesn't grow excessively longer than Japanese list.
-                while self.completed_translations.len() > self.completed_japanese.len() {
-                    self.completed_translations.pop(); // Remove from the end
-                }
-                // Defensive: Ensure every Japanese text has a corresponding translation/placeholder.
-                // New placeholders are inserted at the beginning to match the Japanese text insertion.
-                while self.completed_translations.len() < self.completed_japanese.len() {
-                    self.completed_translations
-                        .insert(0, "[Pending Translation]".to_string());
-                }
-            }
-            AppUpdate::SamplesProcessed(samples) => {
-                self.total_samples_listened += samples;
-                self.raw_samples_count = 0; // Reset after final samples for transcribed segment reported
-            }
-            AppUpdate::RawSamplesDetected(samples) => {
-                self.raw_samples_count += samples;
-            }
-            AppUpdate::Error(err_msg) => {
-                self.status = format!("ERROR: {}", err_msg);
-                // Potentially log to a file or display more prominently
-            }
+/// Where Ctrl+E writes subtitles when `--export` isn't given.
+fn default_export_base(input: &AudioInput, resume: Option<&Path>) -> PathBuf {
+    match (input, resume) {
+        (AudioInput::File(path), _) => path.with_extension(""),
+        (_, Some(session)) => session.with_extension(""),
+        (_, None) => {
+            let started = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            PathBuf::from(format!("transvibe-{}", started.as_secs()))
         }
     }
+}
 
-    fn run(&mut self, terminal: &mut Terminal<impl Backend>) -> Result<()> {
-        while !self.should_quit {
-            terminal.draw(|frame| self.render(frame))?;
-            self.handle_events()?;
-            self.handle_updates();
-        }
-        Ok(())
-    }
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let cli = Cli::parse();
 
-    fn handle_events(&mut self) -> Result<()> {
-        if event::poll(std::time::Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    // Global keybindings for scrolling, etc.
-                    // Check for scroll events first, as they are global.
-                    let mut event_handled = true; // Assume handled if it matches
-                    match (key.code, key.modifiers) {
-                        (KeyCode::Down, KeyModifiers::CONTROL)
-                        | (KeyCode::Char('j'), KeyModifiers::CONTROL) => {
-                            self.scroll_english_down();
-                        }
-                        (KeyCode::Up, KeyModifiers::CONTROL)
-                        | (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
-                            self.scroll_english_up();
-                        }
-                        (KeyCode::Down, KeyModifiers::ALT)
-                        | (KeyCode::Char('j'), KeyModifiers::ALT) => {
-                            self.scroll_japanese_down();
-                        }
-                        (KeyCode::Up, KeyModifiers::ALT)
-                        | (KeyCode::Char('k'), KeyModifiers::ALT) => {
-                            self.scroll_japanese_up();
-                        }
-                        _ => {
-                            event_handled = false; // Not a global scroll key
-                        }
-                    }
-
-                    if event_handled {
-                        return Ok(());
-                    }
-
-                    // Mode-specific keybindings
-                    if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
-                        self.should_quit = true;
-                        return Ok(());
-                    }
-                    match self.input_mode {
-                        AppInputMode::Listening => match key.code {
-                            KeyCode::Char('q') => {
-                                self.should_quit = true;
-                                self.status = "Exiting...".to_string();
-                            }
-                            KeyCode::Char('s') => {
-                                self.input_mode = AppInputMode::StoppedTyping;
-                                self.is_listening_shared.store(false, Ordering::Relaxed);
-                                self.status = "Stopped. Press 's' to Start. Type your message, Enter to process.".to_string();
-                                self.current_live_japanese.clear(); // Clear live transcription
-                                self.user_input.clear(); // Clear previous user input
-                            }
-                            _ => {}
-                        },
-                        AppInputMode::StoppedTyping => match key.code {
-                            KeyCode::Char('q') => {
-                                self.should_quit = true;
-                                self.status = "Exiting...".to_string();
-                            }
-                            KeyCode::Char('s') => {
-                                self.input_mode = AppInputMode::Listening;
-                                self.is_listening_shared.store(true, Ordering::Relaxed);
-                                self.status =
-                                    "Starting... Press 's' to Stop/Start, 'q' to Quit".to_string();
-                                self.user_input.clear();
-                            }
-                            KeyCode::Enter => {
-                                // Process self.user_input (transcribe/translate)
-                                // This part will require sending the user_input to the audio_processing_task
-                                // or a similar new task. For now, we'll just clear it and log.
-                                if !self.user_input.is_empty() {
-                                    // Send user_input for processing. This needs a new AppUpdate variant or mechanism.
-                                    // For now, let's simulate it goes to Japanese history.
-                                    self.completed_japanese
-                                        .push(format!("[User Input]: {}", self.user_input.clone()));
-                                    // Add a placeholder for translation
-                                    if self.completed_translations.len()
-                                        < self.completed_japanese.len()
-                                    {
-                                        self.completed_translations
-                                            .push("Translating user input...".to_string());
-                                    }
-                                    // Here you would ideally trigger a Llama translation for self.user_input
-                                    self.status = format!(
-                                        "Input '{}' submitted. Press 's' to start listening.",
-                                        self.user_input
-                                    );
-                                    self.user_input.clear();
-                                }
-                            }
-                            KeyCode::Char(c) => {
-                                self.user_input.push(c);
-                            }
-                            KeyCode::Backspace => {
-                                self.user_input.pop();
-                            }
-                            _ => {}
-                        },
-                    }
-                }
-            }
-        }
-        Ok(())
+    if let Some(Command::Translate(args)) = &cli.command {
+        return translate_text(&cli, args).await.map_err(|e| eyre!("{e:#}"));
     }
 
-    fn handle_updates(&mut self) {
-        while let Ok(update) = self.rx.try_recv() {
-            self.on_update(update);
+    if cli.list_devices {
+        for device in input_devices().map_err(|e| eyre!("{e:#}"))? {
+            println!("{device}");
         }
+        return Ok(());
     }
 
-    fn render(&mut self, frame: &mut Frame) {
-        // Changed to &mut self
-        let main_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![
-                Constraint::Length(1), // Status
-                Constraint::Length(3), // Live Japanese
-                Constraint::Min(0),    // History
-            ])
-            .split(frame.area());
-
-        // General Status/Help Message
-        let help_text = match self.input_mode {
-            AppInputMode::Listening => {
-                format!(
-                    "Status: {} ({} samples processed) (Press 's' to Stop, 'q' to Quit)",
-                    self.status, self.total_samples_listened
-                )
-            }
-            AppInputMode::StoppedTyping => {
-                "Status: ".to_string()
-                    + &self.status
-                    + " (Press 's' to Start, 'q' to Quit, Enter to submit input)"
-            }
-        };
-        let help_paragraph = Paragraph::new(help_text).style(Style::default().fg(Color::Yellow));
-        frame.render_widget(help_paragraph, main_layout[0]);
-
-        // Input Area (Live Japanese or User Text Input)
-        let input_area_title = match self.input_mode {
-            AppInputMode::Listening => "Live Japanese Input (Listening...)",
-            AppInputMode::StoppedTyping => "Text Input (Stopped - Type here)",
-        };
-        let input_block = Block::default()
-            .title(input_area_title)
-            .borders(Borders::ALL);
-
-        let text_to_display_in_input_area = match self.input_mode {
-            AppInputMode::Listening => self.current_live_japanese.as_str(),
-            AppInputMode::StoppedTyping => self.user_input.as_str(),
-        };
-
-        let mut text_widget = Paragraph::new(text_to_display_in_input_area)
-            .wrap(Wrap { trim: true })
-            .block(input_block.clone());
-
-        if self.input_mode == AppInputMode::StoppedTyping {
-            text_widget = text_widget.style(Style::default().fg(Color::Cyan)); // Style for typing mode
-            // Set cursor position for typing mode
-            #[allow(clippy::cast_possible_truncation)]
-            frame.set_cursor_position(Position::new(
-                main_layout[1].x + self.user_input.chars().count() as u16 + 1,
-                main_layout[1].y + 1,
-            ));
-        } else if self.current_live_japanese.is_empty()
-            && self.input_mode == AppInputMode::Listening
-            && self.status.contains("Listening")
-        {
-            let listening_text = format!(
-                "Listening... ({} samples processed this segment)",
-                self.raw_samples_count
-            );
-            let listening_placeholder = Paragraph::new(listening_text)
-                .wrap(Wrap { trim: true })
-                .block(input_block)
-                .style(Style::default().add_modifier(Modifier::ITALIC));
-            frame.render_widget(listening_placeholder, main_layout[1]);
+    let pcm_format = PcmFormat {
+        sample_format: if cli.rtp {
+            SampleFormat::S16Be
         } else {
-            frame.render_widget(text_widget.clone(), main_layout[1]);
-        }
-        // If it's StoppedTyping mode, render text_widget again to ensure cursor is handled correctly
-        // This is a bit redundant but ensures the cursor logic from above is effective
-        // This is needed because we might have rendered the "Listening..." placeholder.
-        if self.input_mode == AppInputMode::StoppedTyping {
-            frame.render_widget(text_widget, main_layout[1]);
+            cli.pcm_format
+        },
+        sample_rate: cli.pcm_rate,
+        channels: cli.pcm_channels,
+    };
+    let input = match (cli.input, cli.udp) {
+        (Some(path), _) => {
+            // Probe the file now so a bad path fails before the models start loading.
+            open_audio_file(&path).map_err(|e| eyre!("{e:#}"))?;
+            AudioInput::File(path)
         }
+        (None, Some(addr)) => AudioInput::Udp {
+            addr,
+            config: UdpConfig {
+                format: pcm_format,
+                rtp: cli.rtp,
+                jitter_packets: cli.jitter_packets,
+            },
+        },
+        (None, None) if cli.stdin_pcm => AudioInput::Stdin(pcm_format),
+        (None, None) => AudioInput::Capture {
+            endpoint: match &cli.device {
+                Some(wanted) => Some(
+                    find_input_device(wanted)
+                        .map_err(|e| eyre!("{e:#}"))?
+                        .endpoint,
+                ),
+                None => None,
+            },
+        },
+    };
+    let device_switching = matches!(input, AudioInput::Capture { .. });
+    let headless = cli.headless;
+    // Piped audio can't be replayed, so there is nothing left to do once it ends. Headless
+    // runs can't type text to translate either, so they are done at the end of a file, too.
+    let quit_when_finished = match input {
+        AudioInput::Stdin(_) => true,
+        AudioInput::File(_) => headless,
+        AudioInput::Capture { .. } | AudioInput::Udp { .. } => false,
+    };
+    let glossary = match &cli.glossary {
+        Some(path) => Glossary::load(path).map_err(|e| eyre!("{e:#}"))?,
+        None => Glossary::default(),
+    };
+    let resumed = match &cli.resume {
+        Some(path) => Some(Session::load(path).map_err(|e| eyre!("{e:#}"))?),
+        None => None,
+    };
+    let input_label = input.label();
+    let auto_export = cli.export.is_some();
+    let export_base = cli
+        .export
+        .unwrap_or_else(|| default_export_base(&input, cli.resume.as_deref()));
+    let session_path = cli.save.or(cli.resume).unwrap_or_else(|| {
+        let mut file_name = export_base.as_os_str().to_owned();
+        file_name.push(".session.json");
+        PathBuf::from(file_name)
+    });
+    let languages = match &resumed {
+        Some(session) => session
+            .resume_languages(cli.source_lang, cli.target_lang)
+            .map_err(|e| eyre!("{e:#}"))?,
+        None => LanguagePair {
+            source: cli.source_lang.unwrap_or(Language::JAPANESE),
+            target: cli.target_lang.unwrap_or(Language::ENGLISH),
+        },
+    };
+    let file_settings = match &cli.config {
+        Some(path) => ModelSettings::load(path).map_err(|e| eyre!("{e:#}"))?,
+        None => ModelSettings::default(),
+    };
+    let models = file_settings
+        .merge(ModelSettings {
+            whisper: cli.whisper_model,
+            model_dir: cli.model_dir,
+            translate: cli.transcribe_only.then_some(false),
+            llm: LlmSettings {
+                preset: cli.llm,
+                model: cli.llm_gguf,
+                tokenizer: cli.llm_tokenizer,
+            },
+        })
+        .resolve(languages.source)
+        .map_err(|e| eyre!("{e:#}"))?;
+    let transcribe_only = models.llm.is_none();
+
+    let config = PipelineConfig {
+        input,
+        languages,
+        clock_offset: resumed.as_ref().map(Session::end_time).unwrap_or_default(),
+        translation: TranslationConfig {
+            context: ContextConfig {
+                max_pairs: cli.context_segments,
+                token_budget: cli.context_tokens,
+            },
+            queue: QueueConfig {
+                capacity: cli.queue_size,
+                policy: cli.backlog,
+            },
+            glossary,
+        },
+        models,
+    };
 
-        let history_layout = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(main_layout[2]);
-
-        // Japanese Transcript Panel
-        let japanese_lines: Vec<Line> = self
-            .completed_japanese
-            .iter()
-            .enumerate()
-            .flat_map(|(i, s)| {
-                let style = if i == 0 {
-                    // Highlight the first line (newest)
-                    Style::new().fg(Color::White)
-                } else {
-                    Style::new().fg(Color::DarkGray)
-                };
-                let content_line = Line::from(s.as_str()).style(style);
-                if i == 0 {
-                    // Newest item, don't add preceding blank line
-                    vec![content_line]
-                } else {
-                    // Add a blank line before older items for separation
-                    vec![Line::from(""), content_line]
-                }
-            })
-            .collect();
-
-        let japanese_block = Block::default()
-            .title("Japanese Transcript")
-            .borders(Borders::ALL);
-        let common_wrap_setting = Wrap { trim: true };
-
-        // Set scrollbar content length to number of items
-        self.japanese_scroll_state = self
-            .japanese_scroll_state
-            .content_length(self.completed_japanese.len());
-
-        // Auto-scroll logic removed. Scrolling is now manual.
-
-        let japanese_paragraph = Paragraph::new(japanese_lines)
-            .block(japanese_block)
-            .wrap(common_wrap_setting)
-            .scroll((self.japanese_scroll as u16, 0));
-        frame.render_widget(japanese_paragraph, history_layout[0]);
-        frame.render_stateful_widget(
-            Scrollbar::new(ScrollbarOrientation::VerticalRight)
-                .begin_symbol(Some("↑"))
-                .end_symbol(Some("↓")),
-            history_layout[0], // Render scrollbar in the same area
-            &mut self.japanese_scroll_state,
-        );
-
-        // English Translation Panel
-        let english_lines: Vec<Line> = self
-            .completed_translations
-            .iter()
-            .enumerate()
-            .flat_map(|(i, s)| {
-                let style = if i == 0 {
-                    // Highlight the first line (newest)
-                    Style::new().fg(Color::White)
-                } else {
-                    Style::new().fg(Color::DarkGray)
-                };
-                let content_line = Line::from(s.as_str()).style(style);
-                if i == 0 {
-                    // Newest item
-                    vec![content_line]
-                } else {
-                    // Add a blank line before older items
-                    vec![Line::from(""), content_line]
-                }
-            })
-            .collect();
-
-        let english_block = Block::default()
-            .title("English Translation")
-            .borders(Borders::ALL);
-        // Assuming same wrap setting as Japanese panel, can be customized if needed
-        let common_wrap_setting = Wrap { trim: true };
-
-        // Set scrollbar content length to number of items
-        self.english_scroll_state = self
-            .english_scroll_state
-            .content_length(self.completed_translations.len());
-
-        // Auto-scroll logic removed. Scrolling is now manual.
-
-        let english_paragraph = Paragraph::new(english_lines)
-            .block(english_block)
-            .wrap(common_wrap_setting)
-            .scroll((self.english_scroll, 0));
-        frame.render_widget(english_paragraph, history_layout[1]);
-        frame.render_stateful_widget(
-            Scrollbar::new(ScrollbarOrientation::VerticalRight)
-                .begin_symbol(Some("↑"))
-                .end_symbol(Some("↓")),
-            history_layout[1], // Render scrollbar in the same area
-            &mut self.english_scroll_state,
-        );
-    }
+    let (tx, rx) = mpsc::channel(32); // Channel for AppUpdates
+    let (control_tx, control_rx) = mpsc::channel(8); // Channel for PipelineCommands
 
-    fn scroll_japanese_down(&mut self) {
-        let content_height = self.completed_japanese.len();
-        // Assuming roughly one line per item for simplicity in limiting scroll.
-        // A more precise calculation might involve the actual rendered height if lines wrap.
-        if content_height > 0 {
-            // Check if there's content to scroll
-            self.japanese_scroll = self.japanese_scroll.saturating_add(1);
-            // Prevent scrolling beyond content. The paragraph widget itself might also clamp this.
-            // This is a basic clamp; true max scroll depends on viewport height vs content height.
-            if self.japanese_scroll >= content_height {
-                self.japanese_scroll = content_height.saturating_sub(1);
-            }
+    // Clone tx for the audio processing task
+    let tx_audio = tx.clone();
+    tokio::spawn(async move {
+        if let Err(e) = audio_processing_task(tx_audio, control_rx, config).await {
+            // Send error to UI if task fails
+            // The tx channel might be closed if the main app loop has already exited.
+            // We use a let _ to ignore the result of the send, as there's not much we can do
+            // if sending fails here (the UI part is likely gone).
+            let _ = tx
+                .send(AppUpdate::PipelineFailed(format!(
+                    "Audio processing task failed: {:#}",
+                    e
+                )))
+                .await;
         }
-        self.japanese_scroll_state = self.japanese_scroll_state.position(self.japanese_scroll);
+    });
+
+    let mut app = App::new(rx, control_tx) // app needs to be mutable to call run
+        .with_input_label(input_label)
+        .with_device_switching(device_switching)
+        .with_quit_when_finished(quit_when_finished)
+        .with_transcribe_only(transcribe_only)
+        .with_languages(languages)
+        .with_export_base(export_base.clone(), auto_export)
+        .with_session_path(session_path.clone());
+    if let Some(session) = &resumed {
+        app = app.with_session(session);
     }
+    let app_result = if headless {
+        let mut output = HeadlessOutput::new(std::io::stdout(), cli.output, cli.partials);
+        headless::run(&mut app, &mut output)
+            .await
+            .map_err(|e| eyre!("{e:#}"))
+    } else {
+        run_tui(&mut app)
+    };
+
+    // In headless mode stdout carries the results, so notes about files go to stderr.
+    let note = |message: String| {
+        if headless {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+    };
 
-    fn scroll_japanese_up(&mut self) {
-        self.japanese_scroll = self.japanese_scroll.saturating_sub(1);
-        self.japanese_scroll_state = self.japanese_scroll_state.position(self.japanese_scroll);
+    // Keep whatever was transcribed; an empty run doesn't leave a file behind.
+    if !app.segments.is_empty() {
+        match app.session().save(&session_path) {
+            Ok(()) => note(format!("Saved session to {}", session_path.display())),
+            Err(e) => eprintln!("Saving session failed: {:#}", e),
+        }
     }
 
-    fn scroll_english_down(&mut self) {
-        let content_height = self.completed_translations.len() as u16;
-        if content_height > 0 {
-            self.english_scroll = self.english_scroll.saturating_add(1);
-            if self.english_scroll >= content_height {
-                self.english_scroll = content_height.saturating_sub(1);
+    if auto_export {
+        match export_subtitles(&app.segments, languages, transcribe_only, &export_base) {
+            Ok(paths) => {
+                for path in paths {
+                    note(format!("Wrote {}", path.display()));
+                }
             }
+            Err(e) => eprintln!("Export failed: {:#}", e),
         }
-        self.english_scroll_state = self
-            .english_scroll_state
-            .position(self.english_scroll as usize);
     }
 
-    fn scroll_english_up(&mut self) {
-        self.english_scroll = self.english_scroll.saturating_sub(1);
-        self.english_scroll_state = self
-            .english_scroll_state
-            .position(self.english_scroll as usize);
+    if let Err(err) = app_result {
+        // It's good to print the error to stderr if the application fails
+        // before the terminal is fully restored, or if restoration itself fails.
+        eprintln!("Error running app: {:?}", err);
+        // Ensure color_eyre's hook can run by returning the error
+        return Err(err); // No need for .into() if app.run returns color_eyre::Result
     }
+
+    Ok(())
 }
 
-const SYSTEM_PROMPT: &str = "You are an expert translator. Translate the given Japanese text to English accurately and concisely. Output only the English translation. Do not add any pleasantries or extra explanations.";
-
-async fn audio_processing_task(
-    tx: mpsc::Sender<AppUpdate>,
-    is_listening_shared: Arc<AtomicBool>,
-) -> Result<(), anyhow::Error> {
-    tx.send(AppUpdate::StatusUpdate(
-        "Initializing models...".to_string(),
-    ))
-    .await
-    .ok();
-
-    let whisper_model = WhisperBuilder::default()
-        .with_language(Some(WhisperLanguage::Japanese)) // Specify Japanese
-        .build()
-        .await?;
-
-    tx.send(AppUpdate::StatusUpdate(
-        "Whisper model loaded. Initializing Llama...".to_string(),
-    ))
-    .await
-    .ok();
-
-    let llama_model = Llama::builder()
-        .with_source(LlamaSource::qwen_2_5_7b_instruct()) // Or another suitable model
-        .build()
-        .await?;
-    let llama_chat_template = llama_model.chat().with_system_prompt(SYSTEM_PROMPT);
-
-    tx.send(AppUpdate::StatusUpdate(
-        "All models loaded. Listening for microphone input...".to_string(),
-    ))
-    .await
-    .ok();
-
-    let mic_input = MicInput::default();
-    let vad_stream = mic_input.stream().voice_activity_stream();
-    let tx_for_inspect = tx.clone(); // Clone tx for the inspect closure
-    let mut audio_chunks = vad_stream
-        .inspect(move |vad_output| {
-            // vad_output is &VoiceActivityDetectorOutput (or the item type of vad_stream)
-            // This assumes vad_output has a public field `samples` which is a `rodio::buffer::SamplesBuffer<f32>`
-            // as per the user-provided reference.
-            let samples_count = vad_output.samples.clone().count();
-            if samples_count > 0 {
-                // Use try_send to avoid blocking the audio thread.
-                // If the channel is full or disconnected, this will be a no-op.
-                tx_for_inspect
-                    .try_send(AppUpdate::RawSamplesDetected(samples_count))
-                    .ok();
-            }
+/// `transvibe translate`: loads only the translation model and translates lines of text.
+async fn translate_text(cli: &Cli, args: &TranslateArgs) -> Result<(), anyhow::Error> {
+    let languages = LanguagePair {
+        source: cli.source_lang.unwrap_or(Language::JAPANESE),
+        target: cli.target_lang.unwrap_or(Language::ENGLISH),
+    };
+    let glossary = match &cli.glossary {
+        Some(path) => Glossary::load(path)?,
+        None => Glossary::default(),
+    };
+    let file_settings = match &cli.config {
+        Some(path) => ModelSettings::load(path)?,
+        None => ModelSettings::default(),
+    };
+    let (llm, model_dir) = file_settings
+        .merge(ModelSettings {
+            whisper: None,
+            model_dir: cli.model_dir.clone(),
+            translate: None,
+            llm: LlmSettings {
+                preset: cli.llm.clone(),
+                model: cli.llm_gguf.clone(),
+                tokenizer: cli.llm_tokenizer.clone(),
+            },
         })
-        .rechunk_voice_activity()
-        .with_end_window(std::time::Duration::from_millis(400)) // More sensitive end window
-        .with_end_threshold(0.25) // Slightly higher end threshold
-        .with_time_before_speech(std::time::Duration::from_millis(200)); // Reduce pre-speech buffer
-
-    loop {
-        if !is_listening_shared.load(Ordering::Relaxed) {
-            // If not listening, sleep for a bit and check again.
-            // Update status to indicate paused state if desired.
-            // tx.send(AppUpdate::StatusUpdate("Audio processing paused...".to_string())).await.ok();
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-            continue;
-        }
-
-        // Check if there's an audio chunk available without blocking indefinitely if not listening
-        // This might need more sophisticated handling if audio_chunks.next() blocks for too long
-        // when is_listening_shared becomes false during its await.
-        // For simplicity, we proceed with next().await.
-        // A more robust solution might involve a select! with a shutdown signal.
-        let input_audio_chunk = match tokio::time::timeout(
-            std::time::Duration::from_millis(50), // Short timeout to remain responsive to is_listening_shared
-            audio_chunks.next(),
-        )
-        .await
-        {
-            Ok(Some(chunk)) => chunk,
-            Ok(None) => break,  // Stream ended
-            Err(_) => continue, // Timeout, loop back to check is_listening_shared
-        };
-
-        // Indicate that an audio chunk has been received and provide its size
-        let chunk_size = input_audio_chunk.clone().count(); // Get number of samples directly from SamplesBuffer
-        tx.send(AppUpdate::StatusUpdate(format!(
-            "Processing audio chunk ({:#?} samples)...",
-            chunk_size
-        )))
-        .await
-        .ok();
-        // Send the number of samples processed in this chunk for this segment
-        tx.send(AppUpdate::SamplesProcessed(chunk_size)).await.ok();
-
-        // tx.send(AppUpdate::StatusUpdate("Transcribing audio...".to_string()))
-        //     .await
-        //     .ok(); // This line is now replaced by the more specific one above or the one below after transcription
-        let mut current_segment_text = String::new();
-        let mut transcribed_stream = whisper_model.transcribe(input_audio_chunk);
-
-        while let Some(transcribed) = transcribed_stream.next().await {
-            if transcribed.probability_of_no_speech() < 0.85 {
-                current_segment_text.push_str(transcribed.text());
-                tx.send(AppUpdate::LiveJapaneseUpdate(current_segment_text.clone()))
-                    .await
-                    .ok();
-            }
-        }
+        .resolve_translator()?;
 
-        if current_segment_text.trim().chars().count() > 0 {
-            tx.send(AppUpdate::JapaneseSegmentComplete(
-                current_segment_text.clone(),
-            ))
-            .await
-            .ok();
-            tx.send(AppUpdate::StatusUpdate(
-                "Translating to English...".to_string(),
-            ))
-            .await
-            .ok();
-
-            let tx_clone_for_task = tx.clone();
-            let chat_template_for_task = llama_chat_template.clone();
-            let segment_to_translate = current_segment_text.clone();
-
-            tokio::spawn(async move {
-                let prompt = format!(
-                    "Translate the following Japanese text to English, Output only the English translation. Do not add any pleasantries or extra explanations. Do not translate English, keep as is.:\n{}",
-                    segment_to_translate
-                );
-
-                // It's good practice to indicate that the Llama call is starting within the task
-                // tx_clone_for_task.send(AppUpdate::StatusUpdate(
-                //     "Requesting translation from Llama...".to_string(),
-                // ))
-                // .await
-                // .ok();
-
-                let mut llama_chat = chat_template_for_task;
-                let mut response_stream = llama_chat(&prompt);
-                let raw_translation = response_stream.all_text().await;
-                // println!("[Debug Llama Output Live]: {}", raw_translation);
-
-                let cleaned_translation = raw_translation
-                    .replace("<|im_start|>", "")
-                    .replace("<|im_end|>", "")
-                    .trim()
-                    .to_string();
-
-                let _status_translation_excerpt = if cleaned_translation.len() > 20 {
-                    let mut end_index = 20;
-                    if cleaned_translation.is_empty() {
-                        end_index = 0;
-                    } else {
-                        while end_index > 0 && !cleaned_translation.is_char_boundary(end_index) {
-                            end_index -= 1;
-                        }
-                    }
-                    format!("{}...", &cleaned_translation[..end_index])
-                } else {
-                    cleaned_translation.clone()
-                };
-                // This status update can be useful to confirm the task completed
-                // tx_clone_for_task.send(AppUpdate::StatusUpdate(format!(
-                //     "Llama call completed. Got: {}",
-                //     status_translation_excerpt
-                // )))
-                // .await
-                // .ok();
-
-                if !cleaned_translation.is_empty() {
-                    tx_clone_for_task
-                        .send(AppUpdate::EnglishTranslation(cleaned_translation))
-                        .await
-                        .ok();
-                } else {
-                    tx_clone_for_task
-                        .send(AppUpdate::EnglishTranslation(
-                            "[No translation generated]".to_string(),
-                        ))
-                        .await
-                        .ok();
-                }
-            });
+    // Open every file up front so a typo fails before the model loads. `None` is stdin.
+    let mut inputs = Vec::new();
+    for path in &args.files {
+        if path == Path::new("-") {
+            inputs.push(None);
         } else {
-            // Clear live japanese if segment was too short/empty
-            tx.send(AppUpdate::LiveJapaneseUpdate("".to_string()))
-                .await
-                .ok();
+            let file =
+                File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+            inputs.push(Some(BufReader::new(file)));
         }
-        tx.send(AppUpdate::StatusUpdate("Listening...".to_string()))
-            .await
-            .ok();
+    }
+    if inputs.is_empty() {
+        inputs.push(None);
     }
 
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
-
-    let (tx, rx) = mpsc::channel(32); // Channel for AppUpdates
-    let is_listening_shared = Arc::new(AtomicBool::new(true)); // Initially listening
-
-    // Clone tx and is_listening_shared for the audio processing task
-    let tx_audio = tx.clone();
-    let is_listening_audio_task = is_listening_shared.clone();
-    tokio::spawn(async move {
-        if let Err(e) = audio_processing_task(tx_audio, is_listening_audio_task).await {
-            // Send error to UI if task fails
-            // The tx channel might be closed if the main app loop has already exited.
-            // We use a let _ to ignore the result of the send, as there's not much we can do
-            // if sending fails here (the UI part is likely gone).
-            let _ = tx
-                .send(AppUpdate::Error(format!(
-                    "Audio processing task failed: {}",
-                    e
-                )))
-                .await;
+    eprintln!("Loading {}...", llm);
+    let translator = LlamaTranslator::load(
+        languages,
+        glossary.clone(),
+        llm,
+        model_dir.as_deref(),
+        |_| {},
+    )
+    .await?;
+    let config = FilterConfig {
+        batch: args.batch as usize,
+        keep_blank: args.keep_blank,
+        tsv: args.tsv,
+        context: ContextConfig {
+            max_pairs: cli.context_segments,
+            token_budget: cli.context_tokens,
+        },
+    };
+    let mut filter = LineFilter::new(&translator, glossary, std::io::stdout().lock(), config);
+    for input in inputs {
+        match input {
+            Some(file) => filter.read(file).await?,
+            None => filter.read(std::io::stdin().lock()).await?,
         }
-    });
+    }
+    filter.finish().await
+}
 
+/// Runs the TUI until the user quits, restoring the terminal afterwards.
+fn run_tui(app: &mut App) -> Result<()> {
     // Setup terminal
     let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
     crossterm::terminal::enable_raw_mode()?;
@@ -711,7 +515,6 @@ async fn main() -> Result<()> {
     )?;
     terminal.clear()?; // Clear terminal before first draw
 
-    let mut app = App::new(rx); // app needs to be mutable to call run
     let app_result = app.run(&mut terminal); // Pass a mutable reference to terminal
 
     // Restore terminal
@@ -724,14 +527,31 @@ async fn main() -> Result<()> {
 
     // The terminal is dropped here, which should restore the original screen.
     // Explicitly restoring is good practice though.
+    app_result
+}
 
-    if let Err(err) = app_result {
-        // It's good to print the error to stderr if the application fails
-        // before the terminal is fully restored, or if restoration itself fails.
-        eprintln!("Error running app: {:?}", err);
-        // Ensure color_eyre's hook can run by returning the error
-        return Err(err); // No need for .into() if app.run returns color_eyre::Result
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_takes_the_global_options_but_not_the_audio_ones() {
+        let cli = Cli::try_parse_from(["transvibe", "translate", "--llm", "phi-3"]).unwrap();
+        assert_eq!(cli.llm.as_deref(), Some("phi-3"));
+        assert!(matches!(cli.command, Some(Command::Translate(_))));
+
+        for audio_only in [
+            &["--headless"][..],
+            &["--output", "jsonl"],
+            &["--transcribe-only"],
+            &["--export", "subs"],
+            &["--input", "talk.wav"],
+            &["--device", "1"],
+        ] {
+            let before = ["transvibe"].iter().chain(audio_only).chain(&["translate"]);
+            assert!(Cli::try_parse_from(before).is_err(), "{audio_only:?}");
+            let after = ["transvibe", "translate"].iter().chain(audio_only);
+            assert!(Cli::try_parse_from(after).is_err(), "{audio_only:?}");
+        }
     }
-
-    Ok(())
 }
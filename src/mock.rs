@@ -0,0 +1,131 @@
+//! Deterministic, in-memory stand-ins for the model-backed [`Transcriber`] and
+//! [`Translator`], so the pipeline and UI can be exercised without downloading models.
+
+use futures_util::{Stream, stream};
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::transcriber::Transcriber;
+use crate::translator::{ContextPair, Translation, Translator};
+
+/// Replays a fixed script: each audio chunk is "transcribed" as the next line.
+///
+/// Once the script runs out, further chunks transcribe to nothing, the same as silence.
+#[derive(Default)]
+pub struct MockTranscriber {
+    script: Mutex<VecDeque<Vec<String>>>,
+}
+
+impl MockTranscriber {
+    /// Each line is returned as a single segment for one chunk.
+    pub fn new(lines: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self::with_segments(lines.into_iter().map(|line| vec![line.into()]))
+    }
+
+    /// Each entry lists the partial segments streamed for one chunk.
+    pub fn with_segments(chunks: impl IntoIterator<Item = Vec<String>>) -> Self {
+        Self {
+            script: Mutex::new(chunks.into_iter().collect()),
+        }
+    }
+}
+
+impl Transcriber for MockTranscriber {
+    fn transcribe(
+        &self,
+        _audio: SamplesBuffer<f32>,
+    ) -> impl Stream<Item = String> + Send + Unpin + 'static {
+        let segments = self.script.lock().unwrap().pop_front().unwrap_or_default();
+        stream::iter(segments)
+    }
+
+    fn model_name(&self) -> String {
+        "mock".to_string()
+    }
+}
+
+/// Translates by table lookup, falling back to tagging the input as `[en] <text>`.
+#[derive(Default)]
+pub struct MockTranslator {
+    table: HashMap<String, String>,
+    delays: HashMap<String, Duration>,
+    batches: HashMap<Vec<String>, Vec<String>>,
+}
+
+impl MockTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `translation` whenever `source` is translated.
+    pub fn with_entry(mut self, source: impl Into<String>, translation: impl Into<String>) -> Self {
+        self.table.insert(source.into(), translation.into());
+        self
+    }
+
+    /// Makes translating `source` take `delay`, to simulate slow generations finishing out of order.
+    pub fn with_delay(mut self, source: impl Into<String>, delay: Duration) -> Self {
+        self.delays.insert(source.into(), delay);
+        self
+    }
+
+    /// Returns `translations` whenever exactly `sources` are translated together, even if the
+    /// two don't line up, to simulate a model that merges or splits lines.
+    pub fn with_batch(
+        mut self,
+        sources: impl IntoIterator<Item = impl Into<String>>,
+        translations: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.batches.insert(
+            sources.into_iter().map(Into::into).collect(),
+            translations.into_iter().map(Into::into).collect(),
+        );
+        self
+    }
+
+    fn lookup(&self, text: &str) -> String {
+        self.table
+            .get(text)
+            .cloned()
+            .unwrap_or_else(|| format!("[en] {text}"))
+    }
+}
+
+impl Translator for MockTranslator {
+    async fn translate(
+        &self,
+        text: &str,
+        _context: &[ContextPair],
+        mut on_partial: impl FnMut(&str) + Send,
+    ) -> Result<Translation, anyhow::Error> {
+        if let Some(delay) = self.delays.get(text) {
+            tokio::time::sleep(*delay).await;
+        }
+        let translation = self.lookup(text);
+        // Stream it a word at a time, like a model would.
+        let mut partial = String::new();
+        for word in translation.split_inclusive(' ') {
+            partial.push_str(word);
+            on_partial(partial.trim_end());
+        }
+        Ok(Translation::new(translation))
+    }
+
+    /// Translates each line by lookup, unless the whole batch was given with [`MockTranslator::with_batch`].
+    async fn translate_lines(
+        &self,
+        lines: &[String],
+        _context: &[ContextPair],
+    ) -> Result<Vec<String>, anyhow::Error> {
+        if let Some(translations) = self.batches.get(lines) {
+            return Ok(translations.clone());
+        }
+        Ok(lines.iter().map(|line| self.lookup(line)).collect())
+    }
+
+    fn model_name(&self) -> String {
+        "mock".to_string()
+    }
+}
@@ -0,0 +1,833 @@
+//! Which speech and translation models to run, from the command line or a config file.
+//!
+//! A config file is TOML:
+//!
+//! ```toml
+//! whisper = "small"
+//! # load everything from here and never download:
+//! # model_dir = "/models"
+//! # transcribe only, without loading a translation model:
+//! # translate = false
+//!
+//! [llm]
+//! preset = "qwen-2.5-3b-instruct"
+//! # or a local model instead of a preset:
+//! # model = "/models/qwen2.5-3b-instruct-q4_k_m.gguf"
+//! # tokenizer = "/models/tokenizer.json"
+//! ```
+
+use anyhow::{Context, bail};
+use kalosm::language::{FileSource, LlamaSource};
+use kalosm::sound::WhisperSource;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::language::Language;
+
+/// A file kalosm fetches from the Hugging Face hub. Its cache keeps it at
+/// `<repo>/<revision>/<file>`, which is the layout `--model-dir` expects too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubFile {
+    pub repo: String,
+    pub revision: String,
+    pub file: String,
+}
+
+impl HubFile {
+    /// Where the file sits in a kalosm cache rooted at `dir`.
+    pub fn path_in(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.repo).join(&self.revision).join(&self.file)
+    }
+}
+
+/// The weights, tokenizer and config of a Whisper model stored as safetensors.
+const WHISPER_FILES: [&str; 3] = ["model.safetensors", "tokenizer.json", "config.json"];
+/// The same for a quantized Whisper model in a repo of its own.
+const QUANTIZED_WHISPER_FILES: [&str; 3] = ["model.gguf", "tokenizer.json", "config.json"];
+
+/// Whisper models by the names accepted on the command line, with the repo and files rwhisper
+/// fetches for each. rwhisper keeps these private, so `tests/models.rs` checks them against
+/// what it asks its cache for.
+const WHISPER_MODELS: &[(&str, WhisperSource, &str, [&str; 3])] = &[
+    (
+        "tiny",
+        WhisperSource::Tiny,
+        "openai/whisper-tiny",
+        WHISPER_FILES,
+    ),
+    (
+        "quantized-tiny",
+        WhisperSource::QuantizedTiny,
+        "lmz/candle-whisper",
+        [
+            "model-tiny-q80.gguf",
+            "tokenizer-tiny.json",
+            "config-tiny.json",
+        ],
+    ),
+    (
+        "tiny-en",
+        WhisperSource::TinyEn,
+        "openai/whisper-tiny.en",
+        WHISPER_FILES,
+    ),
+    (
+        "quantized-tiny-en",
+        WhisperSource::QuantizedTinyEn,
+        "lmz/candle-whisper",
+        [
+            "model-tiny-en-q80.gguf",
+            "tokenizer-tiny-en.json",
+            "config-tiny-en.json",
+        ],
+    ),
+    (
+        "base",
+        WhisperSource::Base,
+        "openai/whisper-base",
+        WHISPER_FILES,
+    ),
+    (
+        "base-en",
+        WhisperSource::BaseEn,
+        "openai/whisper-base.en",
+        WHISPER_FILES,
+    ),
+    (
+        "small",
+        WhisperSource::Small,
+        "openai/whisper-small",
+        WHISPER_FILES,
+    ),
+    (
+        "small-en",
+        WhisperSource::SmallEn,
+        "openai/whisper-small.en",
+        WHISPER_FILES,
+    ),
+    (
+        "medium",
+        WhisperSource::Medium,
+        "openai/whisper-medium",
+        WHISPER_FILES,
+    ),
+    (
+        "medium-en",
+        WhisperSource::MediumEn,
+        "openai/whisper-medium.en",
+        WHISPER_FILES,
+    ),
+    (
+        "distil-medium-en",
+        WhisperSource::DistilMediumEn,
+        "distil-whisper/distil-medium.en",
+        WHISPER_FILES,
+    ),
+    (
+        "quantized-distil-medium-en",
+        WhisperSource::QuantizedDistilMediumEn,
+        "Demonthos/candle-quantized-whisper-medium-distil",
+        QUANTIZED_WHISPER_FILES,
+    ),
+    (
+        "large",
+        WhisperSource::Large,
+        "openai/whisper-large",
+        WHISPER_FILES,
+    ),
+    (
+        "large-v2",
+        WhisperSource::LargeV2,
+        "openai/whisper-large-v2",
+        WHISPER_FILES,
+    ),
+    (
+        "distil-large-v2",
+        WhisperSource::DistilLargeV2,
+        "distil-whisper/distil-large-v2",
+        WHISPER_FILES,
+    ),
+    (
+        "distil-large-v3",
+        WhisperSource::DistilLargeV3,
+        "distil-whisper/distil-large-v3",
+        WHISPER_FILES,
+    ),
+    (
+        "quantized-distil-large-v3",
+        WhisperSource::QuantizedDistilLargeV3,
+        "Demonthos/candle-quantized-whisper-distil-v3",
+        QUANTIZED_WHISPER_FILES,
+    ),
+    (
+        "quantized-large-v3-turbo",
+        WhisperSource::QuantizedLargeV3Turbo,
+        "Demonthos/candle-quantized-whisper-large-v3-turbo",
+        QUANTIZED_WHISPER_FILES,
+    ),
+];
+
+/// rwhisper fetches every model at this revision.
+const WHISPER_REVISION: &str = "main";
+
+type LlmPreset = fn() -> LlamaSource;
+
+/// Chat models built into kalosm, by the names accepted on the command line.
+const LLM_PRESETS: &[(&str, LlmPreset)] = &[
+    (
+        "qwen-2.5-0.5b-instruct",
+        LlamaSource::qwen_2_5_0_5b_instruct,
+    ),
+    (
+        "qwen-2.5-1.5b-instruct",
+        LlamaSource::qwen_2_5_1_5b_instruct,
+    ),
+    ("qwen-2.5-3b-instruct", LlamaSource::qwen_2_5_3b_instruct),
+    ("qwen-2.5-7b-instruct", LlamaSource::qwen_2_5_7b_instruct),
+    ("llama-3.2-1b-chat", LlamaSource::llama_3_2_1b_chat),
+    ("llama-3.2-3b-chat", LlamaSource::llama_3_2_3b_chat),
+    ("llama-3.1-8b-chat", LlamaSource::llama_3_1_8b_chat),
+    (
+        "phi-3.5-mini-instruct",
+        LlamaSource::phi_3_5_mini_4k_instruct,
+    ),
+    ("phi-4", LlamaSource::phi_4),
+    ("mistral-7b-instruct", LlamaSource::mistral_7b_instruct_2),
+];
+
+/// The hub files a kalosm preset is built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PresetFiles {
+    model: HubFile,
+    tokenizer: Option<HubFile>,
+    /// Only some presets have one, e.g. for Llama 3.1's rope scaling.
+    config: Option<HubFile>,
+}
+
+impl PresetFiles {
+    /// kalosm keeps a source's files private, so they are read back from its debug output,
+    /// where each is `<field>: HuggingFace { model_id: "…", revision: "…", file: "…" }`,
+    /// wrapped in `Some(…)` for the optional ones.
+    fn of(source: &LlamaSource) -> Self {
+        let debug = format!("{source:?}");
+        Self {
+            model: hub_file_field(&debug, "model")
+                .expect("kalosm presets fetch their model from the hub"),
+            tokenizer: hub_file_field(&debug, "tokenizer"),
+            config: hub_file_field(&debug, "config"),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &HubFile> {
+        std::iter::once(&self.model)
+            .chain(&self.tokenizer)
+            .chain(&self.config)
+    }
+}
+
+fn hub_file_field(debug: &str, field: &str) -> Option<HubFile> {
+    let label = format!(" {field}: ");
+    let value = &debug[debug.find(&label)? + label.len()..];
+    let value = value.strip_prefix("Some(").unwrap_or(value);
+    let value = value.strip_prefix("HuggingFace { ")?;
+    let quoted = |key: &str| {
+        let key = format!("{key}: \"");
+        let start = value.find(&key)? + key.len();
+        let end = start + value[start..].find('"')?;
+        Some(value[start..end].to_string())
+    };
+    Some(HubFile {
+        repo: quoted("model_id")?,
+        revision: quoted("revision")?,
+        file: quoted("file")?,
+    })
+}
+
+fn names<'a>(table: impl IntoIterator<Item = &'a str>) -> String {
+    table.into_iter().collect::<Vec<_>>().join(", ")
+}
+
+/// A Whisper model size, such as `small` or `quantized-large-v3-turbo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WhisperModel(&'static str);
+
+impl WhisperModel {
+    /// Every model, in the order they are listed in help text.
+    pub fn all() -> impl Iterator<Item = WhisperModel> {
+        WHISPER_MODELS.iter().map(|(name, ..)| WhisperModel(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+
+    pub fn source(&self) -> WhisperSource {
+        self.entry().1
+    }
+
+    /// The files kalosm needs to load this model.
+    pub fn files(&self) -> Vec<HubFile> {
+        let (_, _, repo, files) = self.entry();
+        files
+            .iter()
+            .map(|file| HubFile {
+                repo: repo.to_string(),
+                revision: WHISPER_REVISION.to_string(),
+                file: file.to_string(),
+            })
+            .collect()
+    }
+
+    fn entry(&self) -> &'static (&'static str, WhisperSource, &'static str, [&'static str; 3]) {
+        WHISPER_MODELS
+            .iter()
+            .find(|(name, ..)| *name == self.0)
+            .expect("WhisperModel is only built from WHISPER_MODELS")
+    }
+}
+
+impl Default for WhisperModel {
+    fn default() -> Self {
+        WhisperModel("quantized-large-v3-turbo")
+    }
+}
+
+impl fmt::Display for WhisperModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl FromStr for WhisperModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.trim().to_ascii_lowercase().replace('_', "-");
+        WHISPER_MODELS
+            .iter()
+            .find(|(name, ..)| *name == wanted)
+            .map(|(name, ..)| WhisperModel(name))
+            .ok_or_else(|| {
+                format!(
+                    "unknown Whisper model '{s}' (expected one of: {})",
+                    names(WHISPER_MODELS.iter().map(|(name, ..)| *name))
+                )
+            })
+    }
+}
+
+/// Where the translation model comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LlmSource {
+    /// One of kalosm's built-in chat models, downloaded on first use.
+    Preset(&'static str),
+    /// A GGUF model on disk, with the tokenizer that goes with it.
+    Local { model: PathBuf, tokenizer: PathBuf },
+}
+
+impl LlmSource {
+    pub fn preset(name: &str) -> Result<Self, String> {
+        let wanted = name.trim().to_ascii_lowercase().replace('_', "-");
+        LLM_PRESETS
+            .iter()
+            .find(|(preset, ..)| *preset == wanted)
+            .map(|(preset, ..)| LlmSource::Preset(preset))
+            .ok_or_else(|| {
+                format!(
+                    "unknown LLM preset '{name}' (expected one of: {}, or a local GGUF file)",
+                    names(LLM_PRESETS.iter().map(|(preset, ..)| *preset))
+                )
+            })
+    }
+
+    /// What kalosm loads the model from. With a `model_dir`, a preset's files are read from
+    /// there by path, so kalosm has nothing to download; everything else about the preset,
+    /// such as its stop token, stays as kalosm sets it.
+    pub fn llama_source(&self, model_dir: Option<&Path>) -> LlamaSource {
+        match (self, model_dir) {
+            (LlmSource::Preset(name), None) => (preset(name).1)(),
+            (LlmSource::Preset(name), Some(dir)) => {
+                let source = (preset(name).1)();
+                let files = PresetFiles::of(&source);
+                let local = |file: &HubFile| FileSource::Local(file.path_in(dir));
+                let mut source = source.with_model(local(&files.model));
+                if let Some(tokenizer) = &files.tokenizer {
+                    source = source.with_tokenizer(local(tokenizer));
+                }
+                if let Some(config) = &files.config {
+                    source = source.with_config(local(config));
+                }
+                source
+            }
+            (LlmSource::Local { model, tokenizer }, _) => {
+                LlamaSource::new(FileSource::Local(model.clone()))
+                    .with_tokenizer(FileSource::Local(tokenizer.clone()))
+            }
+        }
+    }
+
+    /// The files kalosm fetches for a preset. Local models are not fetched at all.
+    pub fn hub_files(&self) -> Vec<HubFile> {
+        match self {
+            LlmSource::Preset(name) => PresetFiles::of(&(preset(name).1)())
+                .iter()
+                .cloned()
+                .collect(),
+            LlmSource::Local { .. } => Vec::new(),
+        }
+    }
+}
+
+fn preset(name: &str) -> &'static (&'static str, LlmPreset) {
+    LLM_PRESETS
+        .iter()
+        .find(|(preset, ..)| *preset == name)
+        .expect("LlmSource::Preset is only built from LLM_PRESETS")
+}
+
+impl Default for LlmSource {
+    fn default() -> Self {
+        LlmSource::Preset("qwen-2.5-7b-instruct")
+    }
+}
+
+impl fmt::Display for LlmSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmSource::Preset(name) => f.write_str(name),
+            LlmSource::Local { model, .. } => match model.file_name() {
+                Some(file_name) => write!(f, "{}", file_name.to_string_lossy()),
+                None => write!(f, "{}", model.display()),
+            },
+        }
+    }
+}
+
+/// The models to load, checked and ready to go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelConfig {
+    pub whisper: WhisperModel,
+    /// `None` in transcribe-only mode, where no translation model is loaded.
+    pub llm: Option<LlmSource>,
+    /// Load models only from this directory, laid out like kalosm's download cache.
+    /// `None` downloads whatever isn't cached yet.
+    pub model_dir: Option<PathBuf>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            whisper: WhisperModel::default(),
+            llm: Some(LlmSource::default()),
+            model_dir: None,
+        }
+    }
+}
+
+impl ModelConfig {
+    /// The files under `dir` these models need that aren't there.
+    pub fn missing_files(&self, dir: &Path) -> Vec<PathBuf> {
+        self.whisper
+            .files()
+            .into_iter()
+            .chain(self.llm.iter().flat_map(LlmSource::hub_files))
+            .map(|file| file.path_in(dir))
+            .filter(|path| !path.is_file())
+            .collect()
+    }
+}
+
+/// Model choices as written in a config file or on the command line, before validation.
+/// Anything left unset falls back to the defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSettings {
+    pub whisper: Option<String>,
+    pub model_dir: Option<PathBuf>,
+    /// `false` to transcribe only. The `[llm]` settings are ignored then.
+    pub translate: Option<bool>,
+    #[serde(default)]
+    pub llm: LlmSettings,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LlmSettings {
+    pub preset: Option<String>,
+    pub model: Option<PathBuf>,
+    pub tokenizer: Option<PathBuf>,
+}
+
+impl ModelSettings {
+    /// Reads a TOML config file. Relative model paths are taken from the file's directory.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config {}", path.display()))?;
+        let mut settings: ModelSettings =
+            toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for file in [
+            &mut settings.model_dir,
+            &mut settings.llm.model,
+            &mut settings.llm.tokenizer,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file = dir.join(&*file);
+        }
+        Ok(settings)
+    }
+
+    /// Settings from `overrides` win over these ones.
+    pub fn merge(self, overrides: ModelSettings) -> Self {
+        // Asking for a translation model turns translation back on.
+        let wants_llm = overrides.llm.preset.is_some() || overrides.llm.model.is_some();
+        // A local model on the command line replaces a preset from the file, and vice versa.
+        let llm = if overrides.llm.preset.is_some() {
+            overrides.llm
+        } else if overrides.llm.model.is_some() {
+            LlmSettings {
+                tokenizer: overrides.llm.tokenizer.or(self.llm.tokenizer),
+                ..overrides.llm
+            }
+        } else {
+            LlmSettings {
+                tokenizer: overrides.llm.tokenizer.or(self.llm.tokenizer),
+                ..self.llm
+            }
+        };
+        Self {
+            whisper: overrides.whisper.or(self.whisper),
+            model_dir: overrides.model_dir.or(self.model_dir),
+            translate: overrides
+                .translate
+                .or(wants_llm.then_some(true))
+                .or(self.translate),
+            llm,
+        }
+    }
+
+    /// Checks the choices against what exists, for transcribing `source_language`.
+    pub fn resolve(self, source_language: Language) -> Result<ModelConfig, anyhow::Error> {
+        let whisper = match &self.whisper {
+            Some(name) => name.parse::<WhisperModel>().map_err(anyhow::Error::msg)?,
+            None => WhisperModel::default(),
+        };
+        if !whisper.source().is_multilingual() && source_language != Language::ENGLISH {
+            bail!(
+                "Whisper model '{}' only understands English, but the source language is {}. \
+                 Pick a model without the -en suffix.",
+                whisper,
+                source_language
+            );
+        }
+
+        let llm = match self.translate {
+            Some(false) => None,
+            _ => Some(resolve_llm(self.llm)?),
+        };
+
+        let models = ModelConfig {
+            whisper,
+            llm,
+            model_dir: self.model_dir,
+        };
+        if let Some(dir) = &models.model_dir {
+            let wanted = match &models.llm {
+                Some(llm) => format!("whisper {} and {}", models.whisper, llm),
+                None => format!("whisper {}", models.whisper),
+            };
+            check_model_dir(dir, models.missing_files(dir), &wanted)?;
+        }
+        Ok(models)
+    }
+
+    /// Checks the translation model choice alone, for translating text with no audio involved.
+    pub fn resolve_translator(self) -> Result<(LlmSource, Option<PathBuf>), anyhow::Error> {
+        if self.translate == Some(false) {
+            bail!("Translating text needs a translation model, but translate = false");
+        }
+        let llm = resolve_llm(self.llm)?;
+        if let Some(dir) = &self.model_dir {
+            let missing = llm
+                .hub_files()
+                .into_iter()
+                .map(|file| file.path_in(dir))
+                .filter(|path| !path.is_file())
+                .collect();
+            check_model_dir(dir, missing, &llm.to_string())?;
+        }
+        Ok((llm, self.model_dir))
+    }
+}
+
+fn resolve_llm(settings: LlmSettings) -> Result<LlmSource, anyhow::Error> {
+    Ok(match settings {
+        LlmSettings {
+            preset: Some(_),
+            model: Some(_),
+            ..
+        } => bail!("Choose either an LLM preset or a local GGUF model, not both"),
+        LlmSettings {
+            model: Some(model),
+            tokenizer,
+            ..
+        } => {
+            let Some(tokenizer) = tokenizer else {
+                bail!(
+                    "The local model {} needs a tokenizer.json to go with it",
+                    model.display()
+                );
+            };
+            for (what, path) in [("LLM model", &model), ("tokenizer", &tokenizer)] {
+                if !path.is_file() {
+                    bail!("The {} file {} does not exist", what, path.display());
+                }
+            }
+            if !model
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("gguf"))
+            {
+                bail!("The LLM model {} is not a .gguf file", model.display());
+            }
+            LlmSource::Local { model, tokenizer }
+        }
+        LlmSettings {
+            tokenizer: Some(_), ..
+        } => bail!("A tokenizer was given without a local GGUF model to go with it"),
+        LlmSettings {
+            preset: Some(name), ..
+        } => LlmSource::preset(&name).map_err(anyhow::Error::msg)?,
+        LlmSettings { .. } => LlmSource::default(),
+    })
+}
+
+/// Fails unless `dir` exists and nothing in `missing` (the files needed for `wanted`) is.
+fn check_model_dir(dir: &Path, missing: Vec<PathBuf>, wanted: &str) -> Result<(), anyhow::Error> {
+    if !dir.is_dir() {
+        bail!("The model directory {} does not exist", dir.display());
+    }
+    if !missing.is_empty() {
+        let list: Vec<String> = missing
+            .iter()
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+        bail!(
+            "The model directory {} is missing {} file(s) for {}:\n{}\n\
+             Copy them from the kalosm cache of a machine that has run these models.",
+            dir.display(),
+            missing.len(),
+            wanted,
+            list.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(toml: &str) -> ModelSettings {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// An empty directory of its own for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transvibe-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn command_line_settings_win_over_the_file() {
+        let file =
+            settings("whisper = \"small\"\nmodel_dir = \"/models\"\n[llm]\npreset = \"phi-4\"");
+        let merged = file.merge(settings("whisper = \"tiny\""));
+        assert_eq!(merged.whisper.as_deref(), Some("tiny"));
+        assert_eq!(merged.model_dir, Some(PathBuf::from("/models")));
+        assert_eq!(merged.llm.preset.as_deref(), Some("phi-4"));
+    }
+
+    #[test]
+    fn a_local_model_replaces_a_preset_and_keeps_the_files_tokenizer() {
+        let file = settings("[llm]\npreset = \"phi-4\"\ntokenizer = \"/models/tokenizer.json\"");
+        let merged = file.merge(settings("[llm]\nmodel = \"/models/model.gguf\""));
+        assert_eq!(merged.llm.preset, None);
+        assert_eq!(merged.llm.model, Some(PathBuf::from("/models/model.gguf")));
+        assert_eq!(
+            merged.llm.tokenizer,
+            Some(PathBuf::from("/models/tokenizer.json"))
+        );
+
+        let file = settings("[llm]\nmodel = \"/models/model.gguf\"");
+        let merged = file.merge(settings("[llm]\npreset = \"phi-4\""));
+        assert_eq!(merged.llm.model, None);
+        assert_eq!(merged.llm.preset.as_deref(), Some("phi-4"));
+    }
+
+    #[test]
+    fn asking_for_a_translation_model_turns_translation_back_on() {
+        let file = settings("translate = false");
+        assert_eq!(
+            file.clone()
+                .merge(settings("[llm]\npreset = \"phi-4\""))
+                .translate,
+            Some(true)
+        );
+        assert_eq!(
+            file.clone().merge(settings("whisper = \"tiny\"")).translate,
+            Some(false)
+        );
+        let merged = file.merge(settings("translate = false\n[llm]\npreset = \"phi-4\""));
+        assert_eq!(merged.translate, Some(false));
+    }
+
+    #[test]
+    fn defaults_resolve_to_the_default_models() {
+        let models = ModelSettings::default()
+            .resolve(Language::JAPANESE)
+            .unwrap();
+        assert_eq!(models, ModelConfig::default());
+
+        let models = settings("translate = false")
+            .resolve(Language::JAPANESE)
+            .unwrap();
+        assert_eq!(models.llm, None);
+    }
+
+    #[test]
+    fn english_only_whisper_needs_english_speech() {
+        let english_only = settings("whisper = \"tiny_en\"");
+        assert!(english_only.clone().resolve(Language::JAPANESE).is_err());
+        let models = english_only.resolve(Language::ENGLISH).unwrap();
+        assert_eq!(models.whisper.name(), "tiny-en");
+    }
+
+    #[test]
+    fn conflicting_or_unknown_translation_models_are_refused() {
+        for toml in [
+            "[llm]\npreset = \"phi-4\"\nmodel = \"/models/model.gguf\"",
+            "[llm]\ntokenizer = \"/models/tokenizer.json\"",
+            "[llm]\nmodel = \"/models/model.gguf\"",
+            "[llm]\npreset = \"gpt-5\"",
+        ] {
+            assert!(
+                settings(toml).resolve(Language::JAPANESE).is_err(),
+                "{toml}"
+            );
+        }
+        assert!(settings("translate = false").resolve_translator().is_err());
+    }
+
+    #[test]
+    fn a_local_model_must_be_a_gguf_file_that_exists() {
+        let dir = scratch_dir("local-model");
+        for file in ["model.gguf", "model.bin", "tokenizer.json"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let local = |model: &str| LlmSettings {
+            model: Some(dir.join(model)),
+            tokenizer: Some(dir.join("tokenizer.json")),
+            ..LlmSettings::default()
+        };
+
+        let (llm, model_dir) = ModelSettings {
+            llm: local("model.gguf"),
+            ..ModelSettings::default()
+        }
+        .resolve_translator()
+        .unwrap();
+        assert_eq!(
+            llm,
+            LlmSource::Local {
+                model: dir.join("model.gguf"),
+                tokenizer: dir.join("tokenizer.json"),
+            }
+        );
+        assert_eq!(model_dir, None);
+        for model in ["model.bin", "missing.gguf"] {
+            let settings = ModelSettings {
+                llm: local(model),
+                ..ModelSettings::default()
+            };
+            assert!(settings.resolve_translator().is_err(), "{model}");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_model_dir_must_hold_every_file() {
+        let dir = scratch_dir("model-dir");
+        let settings = ModelSettings {
+            whisper: Some("tiny".to_string()),
+            model_dir: Some(dir.clone()),
+            translate: Some(false),
+            ..ModelSettings::default()
+        };
+        let error = settings.clone().resolve(Language::JAPANESE).unwrap_err();
+        assert!(error.to_string().contains("missing 3 file(s)"), "{error}");
+
+        for file in WhisperModel("tiny").files() {
+            let path = file.path_in(&dir);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let models = settings.resolve(Language::JAPANESE).unwrap();
+        assert_eq!(models.model_dir, Some(dir.clone()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn preset_files_are_the_ones_kalosm_fetches() {
+        use kalosm::language::*;
+        use kalosm_common::Cache;
+
+        for (name, llama_source) in LLM_PRESETS {
+            let dir = scratch_dir(&format!("preset-{name}"));
+            let builder =
+                || Llama::builder().with_source(llama_source().with_cache(Cache::new(dir.clone())));
+            assert!(builder().requires_download(), "{name}");
+            let files = LlmSource::preset(name).unwrap().hub_files();
+            for file in &files {
+                let path = file.path_in(&dir);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, "").unwrap();
+            }
+            // kalosm only checks the model and tokenizer; the count catches a missed config.
+            assert!(!builder().requires_download(), "{name}: {files:?}");
+            let fetched = format!("{:?}", llama_source());
+            assert_eq!(
+                fetched.matches("HuggingFace {").count(),
+                files.len(),
+                "{name}: {fetched}"
+            );
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_model_dir_only_changes_where_preset_files_are_read_from() {
+        let dir = Path::new("/models");
+        for (name, llama_source) in LLM_PRESETS {
+            // The stock preset with each hub file swapped for its path: stop token, group
+            // query attention and the rest must come through untouched.
+            let mut expected = format!("{:?}", llama_source());
+            for file in LlmSource::preset(name).unwrap().hub_files() {
+                let hub = format!(
+                    "HuggingFace {{ model_id: {:?}, revision: {:?}, file: {:?} }}",
+                    file.repo, file.revision, file.file
+                );
+                let local = format!("Local({:?})", file.path_in(dir));
+                assert!(expected.contains(&hub), "{name}: {hub} not in {expected}");
+                expected = expected.replace(&hub, &local);
+            }
+            let source = LlmSource::preset(name).unwrap().llama_source(Some(dir));
+            assert_eq!(format!("{source:?}"), expected, "{name}");
+        }
+    }
+}
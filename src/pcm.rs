@@ -0,0 +1,476 @@
+//! Raw interleaved PCM audio from a byte stream, such as a recorder's stdout or stdin.
+
+use futures_util::{Stream, StreamExt, stream};
+use kalosm::sound::AsyncSource;
+use std::fmt;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use anyhow::Context;
+
+use crate::app::AppUpdate;
+
+/// The rate the voice activity detector and Whisper work at. Raw PCM is resampled to it.
+pub const PIPELINE_SAMPLE_RATE: u32 = 16_000;
+
+/// How each sample is encoded, named as ffmpeg's `-f` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleFormat {
+    /// 32-bit float, little-endian.
+    #[default]
+    F32Le,
+    /// Signed 16-bit integer, little-endian.
+    S16Le,
+    /// Signed 16-bit integer, big-endian, as carried by RTP L16.
+    S16Be,
+    /// Signed 32-bit integer, little-endian.
+    S32Le,
+}
+
+impl SampleFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            SampleFormat::F32Le | SampleFormat::S32Le => 4,
+            SampleFormat::S16Le | SampleFormat::S16Be => 2,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::F32Le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            SampleFormat::S16Le => f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0,
+            SampleFormat::S16Be => f32::from(i16::from_be_bytes([bytes[0], bytes[1]])) / 32768.0,
+            SampleFormat::S32Le => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / 2_147_483_648.0
+            }
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SampleFormat::F32Le => "f32le",
+            SampleFormat::S16Le => "s16le",
+            SampleFormat::S16Be => "s16be",
+            SampleFormat::S32Le => "s32le",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "f32le" | "f32" | "float32le" => Ok(SampleFormat::F32Le),
+            "s16le" | "s16" => Ok(SampleFormat::S16Le),
+            "s16be" => Ok(SampleFormat::S16Be),
+            "s32le" | "s32" => Ok(SampleFormat::S32Le),
+            _ => Err(format!(
+                "unknown sample format '{s}' (expected f32le, s16le, s16be or s32le)"
+            )),
+        }
+    }
+}
+
+/// The layout of a raw PCM stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_format: SampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for PcmFormat {
+    fn default() -> Self {
+        Self {
+            sample_format: SampleFormat::F32Le,
+            sample_rate: PIPELINE_SAMPLE_RATE,
+            channels: 1,
+        }
+    }
+}
+
+/// Turns raw interleaved PCM bytes into mono samples at [`PIPELINE_SAMPLE_RATE`].
+/// Multi-channel audio is downmixed, and a partial frame is kept for the next call.
+pub struct PcmDecoder {
+    format: PcmFormat,
+    pending: Vec<u8>,
+    resampler: Option<Resampler>,
+}
+
+impl PcmDecoder {
+    pub fn new(format: PcmFormat) -> Self {
+        Self {
+            format,
+            pending: Vec::new(),
+            resampler: (format.sample_rate != PIPELINE_SAMPLE_RATE)
+                .then(|| Resampler::new(format.sample_rate, PIPELINE_SAMPLE_RATE)),
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<f32> {
+        let sample_size = self.format.sample_format.bytes_per_sample();
+        let channels = usize::from(self.format.channels.max(1));
+        let frame_size = sample_size * channels;
+        self.pending.extend_from_slice(bytes);
+        let whole = self.pending.len() - self.pending.len() % frame_size;
+        let samples: Vec<f32> = self.pending[..whole]
+            .chunks_exact(frame_size)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(sample_size)
+                    .map(|sample| self.format.sample_format.decode(sample))
+                    .sum();
+                sum / channels as f32
+            })
+            .collect();
+        self.pending.drain(..whole);
+        match &mut self.resampler {
+            Some(resampler) => resampler.process(&samples),
+            None => samples,
+        }
+    }
+}
+
+/// Bytes read from the reader at a time.
+const READ_SIZE: usize = 16 * 1024;
+
+/// Mono audio at [`PIPELINE_SAMPLE_RATE`], decoded on a background thread.
+///
+/// The stream ends when its producer stops, e.g. when a reader reaches end of file or fails.
+pub struct PcmStream {
+    chunks: mpsc::Receiver<Vec<f32>>,
+    /// The process producing the audio, stopped when the stream is dropped. Taken by whoever
+    /// sees it end first: the drop, or the thread watching for it to fail.
+    recorder: Option<Arc<Mutex<Option<Child>>>>,
+}
+
+impl PcmStream {
+    /// Streams samples sent by another thread, which should stop once sending fails.
+    pub fn from_channel(chunks: mpsc::Receiver<Vec<f32>>) -> Self {
+        Self {
+            chunks,
+            recorder: None,
+        }
+    }
+
+    pub fn from_reader(mut reader: impl Read + Send + 'static, format: PcmFormat) -> Self {
+        let (tx, chunks) = mpsc::channel(64);
+        std::thread::spawn(move || {
+            let mut decoder = PcmDecoder::new(format);
+            let mut buffer = vec![0; READ_SIZE];
+            loop {
+                let read = match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                let samples = decoder.decode(&buffer[..read]);
+                if !samples.is_empty() && tx.blocking_send(samples).is_err() {
+                    break; // Nobody is listening any more.
+                }
+            }
+        });
+        Self::from_channel(chunks)
+    }
+
+    /// Records a PulseAudio or PipeWire source, such as a sink's `.monitor`, through `parec`.
+    /// If `parec` fails while recording, the stream ends and what it said is sent to `tx`.
+    pub fn record_pulse_source(
+        source: &str,
+        tx: mpsc::Sender<AppUpdate>,
+    ) -> Result<Self, anyhow::Error> {
+        let format = PcmFormat::default();
+        let mut recorder = Command::new("parec")
+            .arg(format!("--device={source}"))
+            .arg("--format=float32le")
+            .arg(format!("--rate={}", format.sample_rate))
+            .arg("--channels=1")
+            .arg("--latency-msec=50")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Cannot start parec to record from PulseAudio/PipeWire")?;
+        let stdout = recorder
+            .stdout
+            .take()
+            .expect("parec was started with a piped stdout");
+        let mut stderr = recorder
+            .stderr
+            .take()
+            .expect("parec was started with a piped stderr");
+        let recorder = Arc::new(Mutex::new(Some(recorder)));
+        let watched = recorder.clone();
+        let source = source.to_string();
+        std::thread::spawn(move || {
+            // parec closes stderr when it exits, whether it failed or was stopped.
+            let mut said = String::new();
+            stderr.read_to_string(&mut said).ok();
+            // Still there, so nobody stopped it: it ended by itself.
+            let Some(mut recorder) = watched.lock().unwrap().take() else {
+                return;
+            };
+            match recorder.wait() {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    let said = said.trim();
+                    let message = if said.is_empty() {
+                        format!("Recording {source} with parec failed ({status})")
+                    } else {
+                        format!("Recording {source} with parec failed ({status}): {said}")
+                    };
+                    tx.blocking_send(AppUpdate::Error(message)).ok();
+                }
+                Err(e) => {
+                    tx.blocking_send(AppUpdate::Error(format!(
+                        "Cannot tell how parec recording {source} ended: {e}"
+                    )))
+                    .ok();
+                }
+            }
+        });
+        let mut stream = Self::from_reader(stdout, format);
+        stream.recorder = Some(recorder);
+        Ok(stream)
+    }
+}
+
+impl AsyncSource for PcmStream {
+    fn as_stream(&mut self) -> impl Stream<Item = f32> + '_ {
+        stream::poll_fn(|cx| self.chunks.poll_recv(cx)).flat_map(stream::iter)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        PIPELINE_SAMPLE_RATE
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        if let Some(mut recorder) = self
+            .recorder
+            .take()
+            .and_then(|recorder| recorder.lock().unwrap().take())
+        {
+            recorder.kill().ok();
+            recorder.wait().ok();
+        }
+    }
+}
+
+/// Linear-interpolating resampler for mono audio that arrives in chunks. Plenty for speech,
+/// once anything the lower rate can't hold has been filtered out when downsampling, so it
+/// doesn't alias back into the speech band.
+struct Resampler {
+    /// Input samples per output sample.
+    step: f64,
+    /// Where the next output sample falls, in input samples from `last`.
+    position: f64,
+    /// The final sample of the previous chunk, to interpolate across chunk boundaries.
+    last: Option<f32>,
+    /// `None` when upsampling.
+    low_pass: Option<LowPass>,
+}
+
+impl Resampler {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        Self {
+            step: f64::from(from_rate) / f64::from(to_rate),
+            position: 0.0,
+            last: None,
+            low_pass: (to_rate < from_rate).then(|| LowPass::new(from_rate, to_rate)),
+        }
+    }
+
+    fn process(&mut self, chunk: &[f32]) -> Vec<f32> {
+        let filtered;
+        let chunk = match &mut self.low_pass {
+            Some(low_pass) => {
+                filtered = low_pass.process(chunk);
+                &filtered[..]
+            }
+            None => chunk,
+        };
+        let mut input = Vec::with_capacity(chunk.len() + 1);
+        input.extend(self.last);
+        input.extend_from_slice(chunk);
+        let mut output = Vec::new();
+        while self.position + 1.0 < input.len() as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            output.push(input[index] + (input[index + 1] - input[index]) * fraction);
+            self.position += self.step;
+        }
+        if let Some(&last) = input.last() {
+            self.position -= (input.len() - 1) as f64;
+            self.last = Some(last);
+        }
+        output
+    }
+}
+
+/// Blackman-windowed sinc FIR filter that keeps what lies below 90% of the target rate's
+/// Nyquist frequency, at the input rate. It delays the audio by half its length, about a
+/// millisecond.
+struct LowPass {
+    taps: Vec<f32>,
+    /// The last `taps.len() - 1` input samples, oldest first.
+    history: Vec<f32>,
+}
+
+impl LowPass {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        let ratio = f64::from(from_rate) / f64::from(to_rate);
+        // Longer for bigger ratios, so the transition band stays as wide in Hz. Odd, so the
+        // filter is symmetric around its middle tap.
+        let len = (32.0 * ratio).ceil() as usize | 1;
+        // In cycles per input sample.
+        let cutoff = 0.45 / ratio;
+        let middle = (len / 2) as f64;
+        let mut taps: Vec<f64> = (0..len)
+            .map(|n| {
+                let t = n as f64 - middle;
+                let sinc = if t == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * std::f64::consts::PI * cutoff * t).sin() / (std::f64::consts::PI * t)
+                };
+                let phase = 2.0 * std::f64::consts::PI * n as f64 / (len - 1) as f64;
+                sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+            })
+            .collect();
+        // Unity gain at DC.
+        let sum: f64 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+        Self {
+            taps: taps.into_iter().map(|tap| tap as f32).collect(),
+            history: vec![0.0; len - 1],
+        }
+    }
+
+    fn process(&mut self, chunk: &[f32]) -> Vec<f32> {
+        let mut input = std::mem::take(&mut self.history);
+        input.extend_from_slice(chunk);
+        let output = input
+            .windows(self.taps.len())
+            .map(|window| window.iter().zip(&self.taps).map(|(x, tap)| x * tap).sum())
+            .collect();
+        input.drain(..input.len() + 1 - self.taps.len());
+        self.history = input;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(sample_format: SampleFormat, sample_rate: u32, channels: u16) -> PcmFormat {
+        PcmFormat {
+            sample_format,
+            sample_rate,
+            channels,
+        }
+    }
+
+    fn s16le(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn integer_samples_are_scaled_to_plus_or_minus_one() {
+        assert_eq!(SampleFormat::S16Le.decode(&i16::MIN.to_le_bytes()), -1.0);
+        assert_eq!(SampleFormat::S16Le.decode(&16_384i16.to_le_bytes()), 0.5);
+        assert_eq!(SampleFormat::S16Be.decode(&16_384i16.to_be_bytes()), 0.5);
+        assert_eq!(SampleFormat::S32Le.decode(&i32::MIN.to_le_bytes()), -1.0);
+        assert_eq!(SampleFormat::S32Le.decode(&(1i32 << 30).to_le_bytes()), 0.5);
+        assert_eq!(SampleFormat::F32Le.decode(&0.25f32.to_le_bytes()), 0.25);
+    }
+
+    #[test]
+    fn channels_are_averaged_into_one() {
+        let mut decoder = PcmDecoder::new(format(SampleFormat::F32Le, PIPELINE_SAMPLE_RATE, 2));
+        let bytes: Vec<u8> = [0.5f32, -0.5, 1.0, 0.5]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(decoder.decode(&bytes), [0.0, 0.75]);
+    }
+
+    #[test]
+    fn a_frame_split_across_reads_is_decoded_once_it_is_whole() {
+        let format = format(SampleFormat::S16Le, PIPELINE_SAMPLE_RATE, 2);
+        let bytes = s16le(&[16_384, 16_384, -16_384, 0, 8_192, 8_192]);
+        let whole = PcmDecoder::new(format).decode(&bytes);
+        assert_eq!(whole, [0.5, -0.25, 0.25]);
+
+        let mut decoder = PcmDecoder::new(format);
+        let mut pieces = Vec::new();
+        for piece in [&bytes[..1], &bytes[1..3], &bytes[3..7], &bytes[7..]] {
+            pieces.extend(decoder.decode(piece));
+        }
+        assert_eq!(pieces, whole);
+        // Half a frame is held back until the rest of it arrives.
+        assert!(decoder.decode(&bytes[..2]).is_empty());
+        assert_eq!(decoder.decode(&bytes[2..4]), [0.5]);
+    }
+
+    #[test]
+    fn upsampling_interpolates_between_samples() {
+        let mut resampler = Resampler::new(8_000, 16_000);
+        assert_eq!(
+            resampler.process(&[0.0, 1.0, 2.0, 3.0]),
+            [0.0, 0.5, 1.0, 1.5, 2.0, 2.5]
+        );
+        // The last sample of one chunk is interpolated towards the first of the next.
+        assert_eq!(resampler.process(&[4.0]), [3.0, 3.5]);
+    }
+
+    #[test]
+    fn resampling_in_chunks_matches_resampling_all_at_once() {
+        let ramp: Vec<f32> = (0..1_000).map(|i| i as f32).collect();
+        for (from_rate, to_rate) in [(48_000, 16_000), (44_100, 16_000), (8_000, 16_000)] {
+            let all_at_once = Resampler::new(from_rate, to_rate).process(&ramp);
+            let mut resampler = Resampler::new(from_rate, to_rate);
+            let in_chunks: Vec<f32> = ramp
+                .chunks(7)
+                .flat_map(|chunk| resampler.process(chunk))
+                .collect();
+            assert_eq!(in_chunks.len(), all_at_once.len(), "{from_rate} Hz");
+            for (chunked, whole) in in_chunks.iter().zip(&all_at_once) {
+                assert!((chunked - whole).abs() < 1e-3, "{from_rate} Hz");
+            }
+        }
+    }
+
+    /// Root mean square of `samples`, past the filter's start-up.
+    fn rms_after_warm_up(samples: &[f32]) -> f32 {
+        let settled = &samples[200..];
+        (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    fn tone(hz: f32, rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(rate as f32 * seconds) as usize)
+            .map(|i| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn downsampling_filters_out_what_the_lower_rate_cannot_hold() {
+        // Without a low-pass, 12 kHz comes out of 16 kHz as a full-strength 4 kHz tone.
+        let too_high = Resampler::new(48_000, 16_000).process(&tone(12_000.0, 48_000, 1.0));
+        assert!(rms_after_warm_up(&too_high) < 0.01);
+        // Speech frequencies go through untouched.
+        let speech = Resampler::new(48_000, 16_000).process(&tone(1_000.0, 48_000, 1.0));
+        assert!((rms_after_warm_up(&speech) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+}
@@ -0,0 +1,929 @@
+use anyhow::bail;
+use futures_util::{Stream, StreamExt};
+use kalosm::language::ModelLoadingProgress;
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use kalosm::sound::*;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::app::AppUpdate;
+use crate::devices::CaptureEndpoint;
+use crate::glossary::Glossary;
+use crate::input::AudioInput;
+use crate::language::LanguagePair;
+use crate::loading::LoadTracker;
+use crate::models::{LlmSource, ModelConfig};
+use crate::queue::{BacklogPolicy, QueueConfig, Queued, TranslationJob, TranslationQueue};
+use crate::segment::{Segment, SegmentId, TimeSpan, TranslationStatus};
+use crate::session::SessionModels;
+use crate::source::{AudioSource, CaptureSource, FileSource, PcmSource};
+use crate::transcriber::{Transcriber, WhisperTranscriber};
+use crate::translator::{
+    ContextConfig, ContextPair, ContextWindow, LlamaTranslator, Translation, Translator,
+};
+use crate::udp::UdpSource;
+
+/// Requests from the UI to the running pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineCommand {
+    /// Stop capturing. Audio already buffered or being transcribed is discarded.
+    Pause,
+    /// Reopen the capture stream and carry on.
+    Resume,
+    /// Translate typed text as if it had been transcribed. Works while paused, too.
+    TranslateText(String),
+    /// Capture from this endpoint from now on. Only live capture inputs can switch; while
+    /// paused, the new endpoint is opened on resume.
+    SwitchDevice(CaptureEndpoint),
+    /// Stop capturing for good, as when the program is asked to exit. The chunk being
+    /// transcribed is finished and every queued segment is translated before the pipeline
+    /// reports [`PipelineState::Finished`].
+    Stop,
+}
+
+/// What the pipeline is actually doing, as reported back to the UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineState {
+    Loading,
+    Listening,
+    Paused,
+    Finished,
+}
+
+impl std::fmt::Display for PipelineState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            PipelineState::Loading => "Loading",
+            PipelineState::Listening => "Listening",
+            PipelineState::Paused => "Paused",
+            PipelineState::Finished => "Finished",
+        };
+        f.write_str(label)
+    }
+}
+
+/// Everything the default pipeline needs to know before it starts.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineConfig {
+    pub input: AudioInput,
+    pub languages: LanguagePair,
+    /// Where the input's clock starts, e.g. the end of a resumed session.
+    pub clock_offset: Duration,
+    pub translation: TranslationConfig,
+    pub models: ModelConfig,
+}
+
+/// How segments are fed to the translator.
+#[derive(Debug, Clone, Default)]
+pub struct TranslationConfig {
+    pub context: ContextConfig,
+    pub queue: QueueConfig,
+    /// Terms the translator is told to use, and that each translation is checked against.
+    pub glossary: Glossary,
+}
+
+/// Loads the default models and runs the pipeline on the configured input.
+pub async fn audio_processing_task(
+    tx: mpsc::Sender<AppUpdate>,
+    control_rx: mpsc::Receiver<PipelineCommand>,
+    config: PipelineConfig,
+) -> Result<(), anyhow::Error> {
+    let PipelineConfig {
+        input,
+        languages,
+        clock_offset,
+        translation,
+        models,
+    } = config;
+
+    // Listen right away, so a port that is in use fails before the models load.
+    let udp_source = match &input {
+        AudioInput::Udp { addr, config } => Some(UdpSource::bind(*addr, *config, tx.clone())?),
+        _ => None,
+    };
+
+    tx.send(AppUpdate::StatusUpdate(
+        "Initializing models...".to_string(),
+    ))
+    .await
+    .ok();
+
+    let whisper_progress = LoadTracker::new(format!("whisper {}", models.whisper));
+    let whisper_ready = whisper_progress.ready();
+    tx.send(AppUpdate::ModelLoadProgress(whisper_progress.waiting()))
+        .await
+        .ok();
+
+    // The translation model loads alongside Whisper, and carries on loading while we listen.
+    let translator = models.llm.map(|llm| {
+        load_translator(
+            llm,
+            languages,
+            translation.glossary.clone(),
+            models.model_dir.clone(),
+            tx.clone(),
+        )
+    });
+
+    let transcriber = WhisperTranscriber::load(
+        languages.source,
+        models.whisper,
+        models.model_dir.as_deref(),
+        report_progress(whisper_progress, tx.clone()),
+    )
+    .await?;
+    tx.send(AppUpdate::ModelLoadProgress(whisper_ready))
+        .await
+        .ok();
+
+    tx.send(AppUpdate::StatusUpdate(format!(
+        "Whisper model loaded. {} {}...",
+        match input {
+            AudioInput::Capture { .. } | AudioInput::Udp { .. } => "Listening to",
+            AudioInput::File(_) | AudioInput::Stdin(_) => "Processing",
+        },
+        input.label()
+    )))
+    .await
+    .ok();
+
+    match input {
+        AudioInput::Capture { endpoint } => {
+            run_source(
+                CaptureSource::new(endpoint, tx.clone()),
+                clock_offset,
+                &transcriber,
+                translator,
+                translation,
+                tx,
+                control_rx,
+            )
+            .await
+        }
+        AudioInput::File(path) => {
+            run_source(
+                FileSource::open_path(&path)?,
+                clock_offset,
+                &transcriber,
+                translator,
+                translation,
+                tx,
+                control_rx,
+            )
+            .await
+        }
+        AudioInput::Stdin(format) => {
+            run_source(
+                PcmSource::stdin(format),
+                clock_offset,
+                &transcriber,
+                translator,
+                translation,
+                tx,
+                control_rx,
+            )
+            .await
+        }
+        AudioInput::Udp { .. } => {
+            run_source(
+                udp_source.expect("UDP input was bound above"),
+                clock_offset,
+                &transcriber,
+                translator,
+                translation,
+                tx,
+                control_rx,
+            )
+            .await
+        }
+    }
+}
+
+/// Starts loading the translation model in the background. The returned future resolves
+/// once it is ready. Dropping it before then, e.g. because Whisper failed to load, stops the
+/// load.
+fn load_translator(
+    llm: LlmSource,
+    languages: LanguagePair,
+    glossary: Glossary,
+    model_dir: Option<PathBuf>,
+    tx: mpsc::Sender<AppUpdate>,
+) -> impl Future<Output = Result<LlamaTranslator, anyhow::Error>> + Send + 'static {
+    let progress = LoadTracker::new(llm.to_string());
+    let ready = progress.ready();
+    let loading = tokio::spawn(async move {
+        tx.send(AppUpdate::ModelLoadProgress(progress.waiting()))
+            .await
+            .ok();
+        let translator = LlamaTranslator::load(
+            languages,
+            glossary,
+            llm,
+            model_dir.as_deref(),
+            report_progress(progress, tx.clone()),
+        )
+        .await?;
+        tx.send(AppUpdate::ModelLoadProgress(ready)).await.ok();
+        Ok(translator)
+    });
+    let abort = AbortOnDrop(loading.abort_handle());
+    async move {
+        let _abort = abort;
+        loading
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|loaded| loaded)
+    }
+}
+
+/// Aborts a background task when dropped. Aborting a task that has finished does nothing.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Forwards kalosm's loading callbacks for one model to the UI.
+fn report_progress(
+    mut tracker: LoadTracker,
+    tx: mpsc::Sender<AppUpdate>,
+) -> impl FnMut(ModelLoadingProgress) + Send + Sync + 'static {
+    move |progress| {
+        // Callbacks come thick and fast during a download; a dropped one is replaced by the next.
+        tx.try_send(AppUpdate::ModelLoadProgress(tracker.update(progress)))
+            .ok();
+    }
+}
+
+/// A run of speech cut out of the input, placed on the input's clock.
+pub struct SpeechChunk {
+    pub samples: SamplesBuffer<f32>,
+    /// Where the chunk starts, measured from the start of the input.
+    pub start: Duration,
+}
+
+impl SpeechChunk {
+    pub fn end(&self) -> Duration {
+        self.start + rodio::Source::total_duration(&self.samples).unwrap_or_default()
+    }
+}
+
+/// Splits a voice activity stream into chunks of speech, reporting raw sample counts to the UI as they arrive.
+///
+/// Chunks are timestamped on a clock that reads `clock_start` at the first sample of `frames`.
+/// When `total_duration` is known, progress through the input is reported as well.
+pub fn voice_chunks<V>(
+    frames: V,
+    tx: mpsc::Sender<AppUpdate>,
+    total_duration: Option<Duration>,
+    clock_start: Duration,
+) -> impl Stream<Item = SpeechChunk> + Unpin
+where
+    V: Stream<Item = VoiceActivityDetectorOutput> + Unpin,
+{
+    // Microseconds of audio the VAD has consumed. The rechunker only yields a chunk once it has
+    // seen the frame that ends it, so when a chunk comes out this is exactly where it ends.
+    let clock = Arc::new(AtomicU64::new(0));
+    let clock_for_inspect = clock.clone();
+    frames
+        .inspect(move |vad_output| {
+            let samples_count = vad_output.samples.clone().count();
+            if samples_count > 0 {
+                // Use try_send to avoid blocking the audio thread.
+                // If the channel is full or disconnected, this will be a no-op.
+                tx.try_send(AppUpdate::RawSamplesDetected(samples_count))
+                    .ok();
+            }
+            let frame = rodio::Source::total_duration(&vad_output.samples).unwrap_or_default();
+            let previous = Duration::from_micros(
+                clock_for_inspect.fetch_add(frame.as_micros() as u64, Ordering::Relaxed),
+            );
+            let position = previous + frame;
+            // Once a second of audio is plenty; files decode much faster than real time.
+            if let Some(total) = total_duration
+                && position.as_secs() != previous.as_secs()
+            {
+                tx.try_send(AppUpdate::InputProgress { position, total })
+                    .ok();
+            }
+        })
+        .rechunk_voice_activity()
+        .with_end_window(std::time::Duration::from_millis(400)) // More sensitive end window
+        .with_end_threshold(0.25) // Slightly higher end threshold
+        .with_time_before_speech(std::time::Duration::from_millis(200)) // Reduce pre-speech buffer
+        .map(move |samples| {
+            let end = clock_start + Duration::from_micros(clock.load(Ordering::Relaxed));
+            let length = rodio::Source::total_duration(&samples).unwrap_or_default();
+            SpeechChunk {
+                start: end.saturating_sub(length),
+                samples,
+            }
+        })
+}
+
+/// Runs the pipeline on `source`, placing its audio on the input's clock from `clock_offset`.
+/// See [`run_pipeline`] for how it ends.
+pub async fn run_source<A, T, L>(
+    mut source: A,
+    clock_offset: Duration,
+    transcriber: &T,
+    translator: Option<impl Future<Output = Result<L, anyhow::Error>> + Send + 'static>,
+    translation: TranslationConfig,
+    tx: mpsc::Sender<AppUpdate>,
+    control_rx: mpsc::Receiver<PipelineCommand>,
+) -> Result<(), anyhow::Error>
+where
+    A: AudioSource,
+    T: Transcriber,
+    L: Translator,
+{
+    let live = source.is_live();
+    // After a pause or a device switch a live source reopens with a fresh stream, so its clock
+    // is picked up from the wall clock to keep the time in between.
+    let started = Instant::now();
+    let tx_for_audio = tx.clone();
+    run_pipeline(
+        |switched_to: Option<&CaptureEndpoint>| {
+            let stream = match switched_to {
+                Some(endpoint) => source.open_endpoint(endpoint)?,
+                None => source.open()?,
+            };
+            let clock_start = if live {
+                clock_offset + started.elapsed()
+            } else {
+                clock_offset
+            };
+            Ok(voice_chunks(
+                A::detect_voice(stream),
+                tx_for_audio.clone(),
+                source.total_duration(),
+                clock_start,
+            ))
+        },
+        live,
+        transcriber,
+        translator,
+        translation,
+        tx,
+        control_rx,
+    )
+    .await
+}
+
+/// The capture side of the pipeline, which knows how to stop and restart its stream.
+struct Capture<F, S> {
+    open_audio: F,
+    stream: Option<S>,
+    /// The endpoint switched to, if any. `None` opens the input's own endpoint.
+    device: Option<CaptureEndpoint>,
+    /// Live sources keep producing audio while nobody reads it, so they are closed on pause.
+    /// Finite sources such as files just stop being read, and pick up where they left off.
+    live: bool,
+    paused: bool,
+    stopped: bool,
+}
+
+impl<F, S> Capture<F, S>
+where
+    F: FnMut(Option<&CaptureEndpoint>) -> Result<S, anyhow::Error>,
+{
+    fn open(mut open_audio: F, live: bool) -> Result<Self, anyhow::Error> {
+        let stream = Some(open_audio(None)?);
+        Ok(Self {
+            open_audio,
+            stream,
+            device: None,
+            live,
+            paused: false,
+            stopped: false,
+        })
+    }
+
+    /// The stream to read from, or `None` while paused or once stopped.
+    fn active(&mut self) -> Option<&mut S> {
+        if self.paused || self.stopped {
+            None
+        } else {
+            self.stream.as_mut()
+        }
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+        if self.live {
+            // Dropping the stream stops the capture and discards any buffered VAD chunks.
+            self.stream = None;
+        }
+    }
+
+    /// Closes the stream for good; nothing more is read from it.
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.stream = None;
+    }
+
+    fn resume(&mut self) -> Result<(), anyhow::Error> {
+        if self.stream.is_none() {
+            self.stream = Some((self.open_audio)(self.device.as_ref())?);
+        }
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Moves a live capture to another device. The old stream keeps running until the new one
+    /// has opened, so a device that fails to open changes nothing.
+    fn switch_device(&mut self, device: CaptureEndpoint) -> Result<(), anyhow::Error> {
+        if !self.live {
+            bail!("Only live input can switch devices");
+        }
+        if self.stream.is_some() {
+            self.stream = Some((self.open_audio)(Some(&device))?);
+        }
+        self.device = Some(device);
+        Ok(())
+    }
+}
+
+/// Transcribes each speech chunk and queues every finished segment for the translation worker.
+///
+/// `open_audio` is called to start capturing. For `live` inputs it is called again on every
+/// resume, since pausing drops the stream it returned to stop the capture, and with the new
+/// endpoint when switching devices; otherwise it is passed `None`.
+/// `translator` may still be loading: capture starts right away, and segments wait in the
+/// queue until it is ready. Without one, segments are only transcribed.
+/// Once the audio stream ends (or [`PipelineCommand::Stop`] ends the capture) and every pending
+/// translation has been delivered, typed text is still translated until the control channel
+/// closes, and then this returns.
+pub async fn run_pipeline<F, S, T, L>(
+    open_audio: F,
+    live: bool,
+    transcriber: &T,
+    translator: Option<impl Future<Output = Result<L, anyhow::Error>> + Send + 'static>,
+    translation: TranslationConfig,
+    tx: mpsc::Sender<AppUpdate>,
+    mut control_rx: mpsc::Receiver<PipelineCommand>,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(Option<&CaptureEndpoint>) -> Result<S, anyhow::Error>,
+    S: Stream<Item = SpeechChunk> + Unpin,
+    T: Transcriber,
+    L: Translator,
+{
+    let mut capture = Capture::open(open_audio, live)?;
+    let mut control_open = true;
+    let models = SessionModels {
+        transcriber: Some(transcriber.model_name()),
+        translator: None,
+    };
+    tx.send(AppUpdate::ModelsLoaded(models.clone())).await.ok();
+    let mut translations = Translations::start(translator, models, translation, live, tx.clone());
+    tx.send(AppUpdate::PipelineStateChanged(PipelineState::Listening))
+        .await
+        .ok();
+
+    loop {
+        if capture.stopped {
+            break;
+        }
+        let Some(chunks) = capture.active() else {
+            // Paused: nothing is captured until we're told to resume.
+            match control_rx.recv().await {
+                Some(PipelineCommand::Resume) => match capture.resume() {
+                    Ok(()) => {
+                        tx.send(AppUpdate::PipelineStateChanged(PipelineState::Listening))
+                            .await
+                            .ok();
+                    }
+                    Err(e) => {
+                        tx.send(AppUpdate::Error(format!("Cannot resume capture: {:#}", e)))
+                            .await
+                            .ok();
+                    }
+                },
+                Some(PipelineCommand::Pause) => {}
+                Some(PipelineCommand::TranslateText(text)) => {
+                    translations.submit_typed(text).await;
+                }
+                Some(PipelineCommand::SwitchDevice(device)) => {
+                    switch_device(&mut capture, device, &tx).await;
+                }
+                Some(PipelineCommand::Stop) => capture.stop(),
+                None => {
+                    // Nobody can resume us any more.
+                    break;
+                }
+            }
+            continue;
+        };
+
+        let next = tokio::select! {
+            biased;
+            command = control_rx.recv(), if control_open => Next::Command(command),
+            chunk = chunks.next() => Next::Chunk(chunk),
+        };
+        let speech_chunk = match next {
+            Next::Chunk(Some(chunk)) => chunk,
+            Next::Chunk(None) => break, // Stream ended
+            Next::Command(command) => {
+                handle_command(
+                    command,
+                    &mut capture,
+                    &mut control_open,
+                    &mut translations,
+                    &tx,
+                )
+                .await;
+                continue;
+            }
+        };
+        let span = TimeSpan {
+            start: speech_chunk.start,
+            end: speech_chunk.end(),
+        };
+        let input_audio_chunk = speech_chunk.samples;
+
+        // Indicate that an audio chunk has been received and provide its size
+        let chunk_size = input_audio_chunk.clone().count(); // Get number of samples directly from SamplesBuffer
+        tx.send(AppUpdate::StatusUpdate(format!(
+            "Processing audio chunk ({:#?} samples)...",
+            chunk_size
+        )))
+        .await
+        .ok();
+        // Send the number of samples processed in this chunk for this segment
+        tx.send(AppUpdate::SamplesProcessed(chunk_size)).await.ok();
+
+        let mut current_segment_text = String::new();
+        let mut transcribed_stream = transcriber.transcribe(input_audio_chunk);
+        let mut interrupted = false;
+
+        loop {
+            let next = tokio::select! {
+                biased;
+                command = control_rx.recv(), if control_open => Next::Command(command),
+                text = transcribed_stream.next() => Next::Chunk(text),
+            };
+            match next {
+                Next::Chunk(Some(text)) => {
+                    current_segment_text.push_str(&text);
+                    tx.send(AppUpdate::LiveTranscriptUpdate(
+                        current_segment_text.clone(),
+                    ))
+                    .await
+                    .ok();
+                }
+                Next::Chunk(None) => break,
+                Next::Command(command) => {
+                    if handle_command(
+                        command,
+                        &mut capture,
+                        &mut control_open,
+                        &mut translations,
+                        &tx,
+                    )
+                    .await
+                        && capture.live
+                    {
+                        // Paused mid-chunk: drop the half-transcribed segment too.
+                        // Files finish the chunk instead, so resuming doesn't skip anything.
+                        interrupted = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if interrupted {
+            tx.send(AppUpdate::LiveTranscriptUpdate("".to_string()))
+                .await
+                .ok();
+            continue;
+        }
+
+        if current_segment_text.trim().chars().count() > 0 {
+            let segment_id = SegmentId::next();
+            let mut segment = Segment::new(segment_id, current_segment_text.clone());
+            segment.time = Some(span);
+            translations.submit(segment).await;
+        } else {
+            // Clear live transcript if segment was too short/empty
+            tx.send(AppUpdate::LiveTranscriptUpdate("".to_string()))
+                .await
+                .ok();
+        }
+        tx.send(AppUpdate::StatusUpdate("Listening...".to_string()))
+            .await
+            .ok();
+    }
+
+    if !translations.is_empty() {
+        tx.send(AppUpdate::StatusUpdate(
+            "Audio finished. Waiting for the last translations...".to_string(),
+        ))
+        .await
+        .ok();
+    }
+    translations.idle().await;
+
+    tx.send(AppUpdate::StatusUpdate("All audio processed.".to_string()))
+        .await
+        .ok();
+    tx.send(AppUpdate::PipelineStateChanged(PipelineState::Finished))
+        .await
+        .ok();
+
+    // The translator is still loaded, so keep serving typed text until the UI goes away.
+    while control_open && let Some(command) = control_rx.recv().await {
+        if let PipelineCommand::TranslateText(text) = command {
+            translations.submit_typed(text).await;
+        }
+    }
+    translations.finish().await;
+    Ok(())
+}
+
+/// The pipeline's side of the translation worker: hands it segments and reports the backlog.
+struct Translations {
+    queue: Arc<TranslationQueue>,
+    policy: BacklogPolicy,
+    /// `None` when transcribing only.
+    worker: Option<JoinHandle<()>>,
+    tx: mpsc::Sender<AppUpdate>,
+}
+
+impl Translations {
+    /// Starts the worker. Finite inputs always wait for room, since nothing is lost by waiting.
+    ///
+    /// The worker waits for `translator` to load before taking anything off the queue, then
+    /// reports it alongside the transcriber in `models`. Without a translator there is no
+    /// worker, and segments are passed to the UI as they are.
+    fn start<L: Translator>(
+        translator: Option<impl Future<Output = Result<L, anyhow::Error>> + Send + 'static>,
+        models: SessionModels,
+        config: TranslationConfig,
+        live: bool,
+        tx: mpsc::Sender<AppUpdate>,
+    ) -> Self {
+        let queue = Arc::new(TranslationQueue::new(config.queue.capacity));
+        let policy = if live {
+            config.queue.policy
+        } else {
+            BacklogPolicy::Wait
+        };
+        let worker = translator.map(|translator| {
+            // Nothing can be translated before the model is up, so everything said meanwhile
+            // is kept until then, and only then merged or dropped to fit.
+            queue.hold();
+            let (queue, tx) = (queue.clone(), tx.clone());
+            tokio::spawn(async move {
+                let translator = match translator.await {
+                    Ok(translator) => {
+                        tx.send(AppUpdate::ModelsLoaded(SessionModels {
+                            translator: Some(translator.model_name()),
+                            ..models
+                        }))
+                        .await
+                        .ok();
+                        tx.send(AppUpdate::StatusUpdate(
+                            "Translation model loaded.".to_string(),
+                        ))
+                        .await
+                        .ok();
+                        Some(translator)
+                    }
+                    Err(e) => {
+                        tx.send(AppUpdate::Error(format!(
+                            "Loading the translation model failed: {:#}",
+                            e
+                        )))
+                        .await
+                        .ok();
+                        None
+                    }
+                };
+                if translator.is_none() {
+                    queue.release(BacklogPolicy::Wait);
+                } else {
+                    for (segment_id, queued) in queue.release(policy) {
+                        report_queued(&tx, segment_id, queued).await;
+                    }
+                    tx.send(AppUpdate::TranslationQueueDepth(queue.depth()))
+                        .await
+                        .ok();
+                }
+                translation_worker(
+                    queue,
+                    translator,
+                    ContextWindow::new(config.context),
+                    config.glossary,
+                    tx,
+                )
+                .await;
+            })
+        });
+        Self {
+            queue,
+            policy,
+            worker,
+            tx,
+        }
+    }
+
+    /// Hands a finished segment to the UI and queues it for translation.
+    async fn submit(&mut self, mut segment: Segment) {
+        if self.worker.is_none() {
+            segment.status = TranslationStatus::Untranslated;
+            self.tx.send(AppUpdate::SegmentComplete(segment)).await.ok();
+            return;
+        }
+        let job = TranslationJob {
+            segment_id: segment.id,
+            text: segment.source.clone(),
+        };
+        self.tx.send(AppUpdate::SegmentComplete(segment)).await.ok();
+        let segment_id = job.segment_id;
+        let queued = self.queue.push(job, self.policy).await;
+        report_queued(&self.tx, segment_id, queued).await;
+        self.tx
+            .send(AppUpdate::TranslationQueueDepth(self.queue.depth()))
+            .await
+            .ok();
+    }
+
+    /// Typed text becomes a segment of its own. It has no place on the audio clock.
+    async fn submit_typed(&mut self, text: String) {
+        let text = text.trim();
+        if !text.is_empty() {
+            self.submit(Segment::new(SegmentId::next(), text.to_string()))
+                .await;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.depth() == 0
+    }
+
+    /// Waits for every queued translation to be delivered.
+    async fn idle(&self) {
+        self.queue.idle().await;
+    }
+
+    /// Delivers the remaining translations and stops the worker.
+    async fn finish(self) {
+        self.queue.close();
+        if let Some(worker) = self.worker {
+            worker.await.ok();
+        }
+    }
+}
+
+/// Tells the UI what became of segment `segment_id` when it was queued or trimmed.
+async fn report_queued(tx: &mpsc::Sender<AppUpdate>, segment_id: SegmentId, queued: Queued) {
+    let update = match queued {
+        Queued::Added => return,
+        Queued::Dropped(dropped) => AppUpdate::TranslationSkipped(dropped),
+        Queued::Merged { into } => AppUpdate::SegmentsMerged {
+            into,
+            from: segment_id,
+        },
+    };
+    tx.send(update).await.ok();
+}
+
+/// Translates queued segments one at a time, oldest first, until the queue is closed.
+/// Without a translator, every segment fails.
+async fn translation_worker<L: Translator>(
+    queue: Arc<TranslationQueue>,
+    translator: Option<L>,
+    mut context: ContextWindow,
+    glossary: Glossary,
+    tx: mpsc::Sender<AppUpdate>,
+) {
+    while let Some(TranslationJob { segment_id, text }) = queue.next().await {
+        let Some(translator) = &translator else {
+            tx.send(AppUpdate::TranslationFailed(
+                segment_id,
+                "the translation model did not load".to_string(),
+            ))
+            .await
+            .ok();
+            queue.done();
+            tx.send(AppUpdate::TranslationQueueDepth(queue.depth()))
+                .await
+                .ok();
+            continue;
+        };
+        tx.send(AppUpdate::StatusUpdate(format!(
+            "Translating segment {}...",
+            segment_id
+        )))
+        .await
+        .ok();
+        let on_partial = |partial: &str| {
+            // Partials are superseded by the final text, so one lost to a full channel is fine.
+            tx.try_send(AppUpdate::TranslationPartial(
+                segment_id,
+                partial.to_string(),
+            ))
+            .ok();
+        };
+        let update = match translator
+            .translate(&text, &context.recent(), on_partial)
+            .await
+        {
+            Ok(translation) if !translation.text.is_empty() => {
+                let violations = glossary.violations(&text, &translation.text);
+                if !violations.is_empty() {
+                    tx.send(AppUpdate::GlossaryViolations(segment_id, violations))
+                        .await
+                        .ok();
+                }
+                context.push(ContextPair {
+                    source: text,
+                    translation: translation.text.clone(),
+                });
+                AppUpdate::Translation(segment_id, translation)
+            }
+            Ok(_) => {
+                AppUpdate::Translation(segment_id, Translation::new("[No translation generated]"))
+            }
+            Err(e) => AppUpdate::TranslationFailed(segment_id, e.to_string()),
+        };
+        tx.send(update).await.ok();
+        queue.done();
+        tx.send(AppUpdate::TranslationQueueDepth(queue.depth()))
+            .await
+            .ok();
+    }
+}
+
+/// Whichever of a control command or the next stream item arrived first.
+enum Next<T> {
+    Command(Option<PipelineCommand>),
+    Chunk(Option<T>),
+}
+
+/// Applies a command received while capturing. Returns true if the pipeline paused. A stop
+/// lets the chunk being transcribed finish, and ends the capture after it.
+async fn handle_command<F, S>(
+    command: Option<PipelineCommand>,
+    capture: &mut Capture<F, S>,
+    control_open: &mut bool,
+    translations: &mut Translations,
+    tx: &mpsc::Sender<AppUpdate>,
+) -> bool
+where
+    F: FnMut(Option<&CaptureEndpoint>) -> Result<S, anyhow::Error>,
+{
+    match command {
+        Some(PipelineCommand::Pause) => {
+            capture.pause();
+            tx.send(AppUpdate::PipelineStateChanged(PipelineState::Paused))
+                .await
+                .ok();
+            true
+        }
+        Some(PipelineCommand::Resume) => false,
+        Some(PipelineCommand::TranslateText(text)) => {
+            translations.submit_typed(text).await;
+            false
+        }
+        Some(PipelineCommand::SwitchDevice(device)) => {
+            // Audio already cut into a chunk came from the old device and is still good.
+            switch_device(capture, device, tx).await;
+            false
+        }
+        Some(PipelineCommand::Stop) => {
+            capture.stop();
+            false
+        }
+        None => {
+            // The UI went away; keep processing audio without listening for commands.
+            *control_open = false;
+            false
+        }
+    }
+}
+
+/// Switches the capture to `device`, telling the UI which endpoint it is now listening to.
+async fn switch_device<F, S>(
+    capture: &mut Capture<F, S>,
+    device: CaptureEndpoint,
+    tx: &mpsc::Sender<AppUpdate>,
+) where
+    F: FnMut(Option<&CaptureEndpoint>) -> Result<S, anyhow::Error>,
+{
+    let update = match capture.switch_device(device.clone()) {
+        Ok(()) => AppUpdate::InputDeviceChanged(device.to_string()),
+        Err(e) => AppUpdate::Error(format!("Cannot switch to {}: {:#}", device, e)),
+    };
+    tx.send(update).await.ok();
+}
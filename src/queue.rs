@@ -0,0 +1,450 @@
+//! The backlog of segments waiting for the translation worker.
+//!
+//! Segments are translated one at a time, in the order they were queued. The queue is bounded;
+//! what happens when it is full is up to the [`BacklogPolicy`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use crate::segment::SegmentId;
+
+/// What to do with a new segment when the translation queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BacklogPolicy {
+    /// Hold the pipeline until there is room. Nothing is lost, but live input stops being read.
+    Wait,
+    /// Give up on the oldest queued segment to make room.
+    DropOldest,
+    /// Append the new segment to the last queued one, so they are translated together.
+    #[default]
+    Merge,
+}
+
+impl fmt::Display for BacklogPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BacklogPolicy::Wait => "wait",
+            BacklogPolicy::DropOldest => "drop",
+            BacklogPolicy::Merge => "merge",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BacklogPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "wait" => Ok(BacklogPolicy::Wait),
+            "drop" | "drop-oldest" => Ok(BacklogPolicy::DropOldest),
+            "merge" => Ok(BacklogPolicy::Merge),
+            _ => Err(format!(
+                "unknown backlog policy '{s}' (expected wait, drop or merge)"
+            )),
+        }
+    }
+}
+
+/// How far the translation worker may fall behind, and what happens when it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// Segments waiting to be translated, not counting the one being translated.
+    pub capacity: usize,
+    pub policy: BacklogPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4,
+            policy: BacklogPolicy::default(),
+        }
+    }
+}
+
+/// A segment waiting to be translated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslationJob {
+    pub segment_id: SegmentId,
+    pub text: String,
+}
+
+/// What [`TranslationQueue::push`] did with a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queued {
+    /// The job is in the queue.
+    Added,
+    /// The job is in the queue, and this older segment was dropped to make room.
+    Dropped(SegmentId),
+    /// The job's text was appended to the queued segment `into`.
+    Merged { into: SegmentId },
+}
+
+#[derive(Default)]
+struct QueueState {
+    jobs: VecDeque<TranslationJob>,
+    /// The worker has taken a job and not finished it yet.
+    busy: bool,
+    /// Jobs are kept regardless of capacity until [`TranslationQueue::release`].
+    held: bool,
+    closed: bool,
+}
+
+/// A bounded FIFO shared between the pipeline, which pushes, and the worker, which pops.
+pub struct TranslationQueue {
+    state: Mutex<QueueState>,
+    /// Woken whenever a job is pushed, taken or finished, or the queue closes.
+    changed: Notify,
+    capacity: usize,
+}
+
+impl TranslationQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            changed: Notify::new(),
+            // A queue that can't hold anything could never merge or drop into.
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Keeps every job pushed from now on, however full the queue is, until
+    /// [`release`](Self::release). For while the worker is still starting up.
+    pub fn hold(&self) {
+        self.state.lock().unwrap().held = true;
+    }
+
+    /// Queues a job, applying `policy` if the queue is full.
+    pub async fn push(&self, job: TranslationJob, policy: BacklogPolicy) -> Queued {
+        loop {
+            let notified = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let queued = if state.held || state.jobs.len() < self.capacity {
+                    Some(Queued::Added)
+                } else {
+                    match policy {
+                        BacklogPolicy::Wait => None,
+                        BacklogPolicy::DropOldest => state
+                            .jobs
+                            .pop_front()
+                            .map(|dropped| Queued::Dropped(dropped.segment_id)),
+                        BacklogPolicy::Merge => state.jobs.back_mut().map(|last| {
+                            last.text.push(' ');
+                            last.text.push_str(&job.text);
+                            Queued::Merged {
+                                into: last.segment_id,
+                            }
+                        }),
+                    }
+                };
+                if let Some(queued) = queued {
+                    if !matches!(queued, Queued::Merged { .. }) {
+                        state.jobs.push_back(job);
+                    }
+                    drop(state);
+                    self.changed.notify_waiters();
+                    return queued;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Stops holding jobs, and applies `policy` to whatever is over capacity, as if each of
+    /// those jobs had been pushed in turn. Pushes from then on are bounded again; both happen
+    /// under one lock, so none can slip in between. Returns the segments that lost their own
+    /// place in the queue, with what became of each. With [`BacklogPolicy::Wait`], nothing is
+    /// removed; the queue drains by itself.
+    pub fn release(&self, policy: BacklogPolicy) -> Vec<(SegmentId, Queued)> {
+        let mut state = self.state.lock().unwrap();
+        state.held = false;
+        let over = state.jobs.len().saturating_sub(self.capacity);
+        let trimmed: Vec<_> = match policy {
+            BacklogPolicy::Wait => Vec::new(),
+            BacklogPolicy::DropOldest => state
+                .jobs
+                .drain(..over)
+                .map(|dropped| (dropped.segment_id, Queued::Dropped(dropped.segment_id)))
+                .collect(),
+            BacklogPolicy::Merge => {
+                let keep = state.jobs.len() - over;
+                let newest = state.jobs.split_off(keep);
+                let Some(last) = state.jobs.back_mut() else {
+                    return Vec::new();
+                };
+                newest
+                    .into_iter()
+                    .map(|job| {
+                        last.text.push(' ');
+                        last.text.push_str(&job.text);
+                        let into = last.segment_id;
+                        (job.segment_id, Queued::Merged { into })
+                    })
+                    .collect()
+            }
+        };
+        drop(state);
+        if !trimmed.is_empty() {
+            self.changed.notify_waiters();
+        }
+        trimmed
+    }
+
+    /// Takes the oldest job, waiting for one if necessary. Returns `None` once the queue has
+    /// been closed and emptied. Call [`done`](Self::done) when the job is finished.
+    pub async fn next(&self) -> Option<TranslationJob> {
+        loop {
+            let notified = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(job) = state.jobs.pop_front() {
+                    state.busy = true;
+                    drop(state);
+                    self.changed.notify_waiters();
+                    return Some(job);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Marks the job last returned by [`next`](Self::next) as finished.
+    pub fn done(&self) {
+        self.state.lock().unwrap().busy = false;
+        self.changed.notify_waiters();
+    }
+
+    /// Segments queued or being translated.
+    pub fn depth(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.jobs.len() + usize::from(state.busy)
+    }
+
+    /// Waits until every queued job has been translated.
+    pub async fn idle(&self) {
+        loop {
+            let notified = self.changed.notified();
+            if self.depth() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Lets the worker exit once the queue is empty.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    fn job(id: u64, text: &str) -> TranslationJob {
+        TranslationJob {
+            segment_id: SegmentId(id),
+            text: text.to_string(),
+        }
+    }
+
+    /// The queued jobs, taken off the front of a closed queue.
+    fn drain(queue: &TranslationQueue) -> Vec<TranslationJob> {
+        queue.close();
+        let mut jobs = Vec::new();
+        while let Some(job) = queue.next().now_or_never().flatten() {
+            queue.done();
+            jobs.push(job);
+        }
+        jobs
+    }
+
+    #[tokio::test]
+    async fn jobs_come_out_in_the_order_they_went_in() {
+        let queue = TranslationQueue::new(4);
+        for id in 1..=3 {
+            assert_eq!(
+                queue.push(job(id, "x"), BacklogPolicy::Wait).await,
+                Queued::Added
+            );
+        }
+        let ids: Vec<u64> = drain(&queue).iter().map(|job| job.segment_id.0).collect();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_drops_its_oldest_job() {
+        let queue = TranslationQueue::new(2);
+        queue.push(job(1, "a"), BacklogPolicy::DropOldest).await;
+        queue.push(job(2, "b"), BacklogPolicy::DropOldest).await;
+        assert_eq!(
+            queue.push(job(3, "c"), BacklogPolicy::DropOldest).await,
+            Queued::Dropped(SegmentId(1))
+        );
+        assert_eq!(drain(&queue), [job(2, "b"), job(3, "c")]);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_merges_into_its_last_job() {
+        let queue = TranslationQueue::new(2);
+        queue.push(job(1, "a"), BacklogPolicy::Merge).await;
+        queue.push(job(2, "b"), BacklogPolicy::Merge).await;
+        assert_eq!(
+            queue.push(job(3, "c"), BacklogPolicy::Merge).await,
+            Queued::Merged { into: SegmentId(2) }
+        );
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(drain(&queue), [job(1, "a"), job(2, "b c")]);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_waits_for_room() {
+        let queue = TranslationQueue::new(1);
+        queue.push(job(1, "a"), BacklogPolicy::Wait).await;
+        assert!(
+            queue
+                .push(job(2, "b"), BacklogPolicy::Wait)
+                .now_or_never()
+                .is_none()
+        );
+
+        // Taking a job makes room, even before it is done.
+        assert_eq!(queue.next().await, Some(job(1, "a")));
+        assert_eq!(
+            queue.push(job(2, "b"), BacklogPolicy::Wait).await,
+            Queued::Added
+        );
+    }
+
+    #[tokio::test]
+    async fn depth_counts_the_job_being_translated_until_it_is_done() {
+        let queue = TranslationQueue::new(4);
+        queue.push(job(1, "a"), BacklogPolicy::Wait).await;
+        queue.next().await;
+        assert_eq!(queue.depth(), 1);
+        assert!(queue.idle().now_or_never().is_none());
+
+        queue.done();
+        assert_eq!(queue.depth(), 0);
+        assert!(queue.idle().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn closing_lets_the_worker_finish_what_is_queued() {
+        let queue = TranslationQueue::new(4);
+        queue.push(job(1, "a"), BacklogPolicy::Wait).await;
+        queue.close();
+        assert_eq!(queue.next().await, Some(job(1, "a")));
+        queue.done();
+        assert_eq!(queue.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn a_zero_capacity_queue_still_holds_one_job() {
+        let queue = TranslationQueue::new(0);
+        queue.push(job(1, "a"), BacklogPolicy::Merge).await;
+        assert_eq!(
+            queue.push(job(2, "b"), BacklogPolicy::Merge).await,
+            Queued::Merged { into: SegmentId(1) }
+        );
+    }
+
+    #[tokio::test]
+    async fn releasing_drops_the_oldest_jobs_over_capacity() {
+        let queue = TranslationQueue::new(2);
+        queue.hold();
+        for id in 1..=4 {
+            queue.push(job(id, "x"), BacklogPolicy::DropOldest).await;
+        }
+        assert_eq!(
+            queue.release(BacklogPolicy::DropOldest),
+            [
+                (SegmentId(1), Queued::Dropped(SegmentId(1))),
+                (SegmentId(2), Queued::Dropped(SegmentId(2))),
+            ]
+        );
+        let ids: Vec<u64> = drain(&queue).iter().map(|job| job.segment_id.0).collect();
+        assert_eq!(ids, [3, 4]);
+    }
+
+    #[tokio::test]
+    async fn releasing_merges_everything_over_capacity_in_order() {
+        let queue = TranslationQueue::new(2);
+        queue.hold();
+        for (id, text) in [(1, "a"), (2, "b"), (3, "c"), (4, "d")] {
+            queue.push(job(id, text), BacklogPolicy::Merge).await;
+        }
+        let into = Queued::Merged { into: SegmentId(2) };
+        assert_eq!(
+            queue.release(BacklogPolicy::Merge),
+            [(SegmentId(3), into), (SegmentId(4), into)]
+        );
+        assert_eq!(drain(&queue), [job(1, "a"), job(2, "b c d")]);
+    }
+
+    #[tokio::test]
+    async fn releasing_leaves_a_waiting_queue_or_one_within_capacity_alone() {
+        let queue = TranslationQueue::new(2);
+        queue.hold();
+        for id in 1..=3 {
+            queue.push(job(id, "x"), BacklogPolicy::Wait).await;
+        }
+        assert!(queue.release(BacklogPolicy::Wait).is_empty());
+        assert_eq!(queue.depth(), 3);
+        queue.next().await;
+        queue.done();
+        assert!(queue.release(BacklogPolicy::DropOldest).is_empty());
+        assert_eq!(queue.depth(), 2);
+    }
+
+    #[tokio::test]
+    async fn pushes_after_the_release_are_bounded_again() {
+        let queue = TranslationQueue::new(2);
+        queue.hold();
+        for id in 1..=3 {
+            queue.push(job(id, "x"), BacklogPolicy::Merge).await;
+        }
+        assert_eq!(queue.depth(), 3);
+        queue.release(BacklogPolicy::Merge);
+        assert_eq!(
+            queue.push(job(4, "y"), BacklogPolicy::Merge).await,
+            Queued::Merged { into: SegmentId(2) }
+        );
+        assert_eq!(queue.depth(), 2);
+    }
+
+    #[test]
+    fn segments_pushed_while_the_backlog_is_released_stay_within_capacity() {
+        for _ in 0..100 {
+            let queue = TranslationQueue::new(2);
+            queue.hold();
+            for id in 1..=5 {
+                queue
+                    .push(job(id, "x"), BacklogPolicy::Merge)
+                    .now_or_never();
+            }
+            // The model finishes loading while speech keeps coming in.
+            std::thread::scope(|scope| {
+                scope.spawn(|| queue.release(BacklogPolicy::Merge));
+                scope.spawn(|| {
+                    for id in 6..=20 {
+                        queue
+                            .push(job(id, "x"), BacklogPolicy::Merge)
+                            .now_or_never();
+                    }
+                });
+            });
+            assert!(queue.depth() <= 2, "{} queued", queue.depth());
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::glossary::GlossaryEntry;
+use crate::language::Language;
+
+/// Identifies one transcribed segment for the lifetime of the process, so its
+/// translation can find it no matter what order translations finish in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegmentId(pub u64);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+impl SegmentId {
+    /// Allocates a fresh, never-before-used id.
+    pub fn next() -> Self {
+        SegmentId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Marks every id up to and including `self` as used, e.g. after loading a saved session.
+    pub fn reserve_through(self) {
+        NEXT_ID.fetch_max(self.0 + 1, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Display for SegmentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslationStatus {
+    Pending,
+    Done,
+    Failed,
+    /// Dropped from the translation queue because it fell too far behind.
+    Skipped,
+    /// Transcribed in transcribe-only mode, where nothing is translated.
+    Untranslated,
+}
+
+/// Where a segment sits on the input's clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSpan {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// One finished piece of source text and its translation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub id: SegmentId,
+    pub source: String,
+    /// Empty until the translation starts arriving. While `Pending`, this is the text
+    /// generated so far.
+    pub translation: String,
+    pub status: TranslationStatus,
+    /// `None` for text that didn't come from the audio, such as typed input.
+    pub time: Option<TimeSpan>,
+    /// Glossary terms in the source that the translation didn't use.
+    pub glossary_violations: Vec<GlossaryEntry>,
+    /// The translator's remarks about the translation, if any.
+    pub notes: Option<String>,
+    /// Set when the translator thinks the source isn't in the expected language.
+    pub detected_language: Option<Language>,
+}
+
+impl Segment {
+    pub fn new(id: SegmentId, source: String) -> Self {
+        Self {
+            id,
+            source,
+            translation: String::new(),
+            status: TranslationStatus::Pending,
+            time: None,
+            glossary_violations: Vec::new(),
+            notes: None,
+            detected_language: None,
+        }
+    }
+
+    /// What to show in the translation panel for this segment.
+    pub fn translation_display(&self) -> &str {
+        match self.status {
+            TranslationStatus::Pending if self.translation.is_empty() => "Translating...",
+            TranslationStatus::Pending => &self.translation,
+            TranslationStatus::Done => &self.translation,
+            TranslationStatus::Failed => "[Translation failed]",
+            TranslationStatus::Skipped => "[Skipped: translation fell behind]",
+            TranslationStatus::Untranslated => "[Not translated]",
+        }
+    }
+
+    /// Takes in a later segment's text and time, as when the two are translated together.
+    pub fn absorb(&mut self, later: Segment) {
+        self.source.push(' ');
+        self.source.push_str(&later.source);
+        self.time = match (self.time, later.time) {
+            (Some(time), Some(later_time)) => Some(TimeSpan {
+                start: time.start,
+                end: later_time.end,
+            }),
+            (time, later_time) => time.or(later_time),
+        };
+    }
+}
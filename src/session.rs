@@ -0,0 +1,270 @@
+//! Saving a session to disk and loading it back.
+//!
+//! The file is JSON with a `version` field; loaders accept any version up to
+//! [`SESSION_FORMAT_VERSION`] and refuse newer ones rather than guessing.
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::glossary::GlossaryEntry;
+use crate::language::{Language, LanguagePair};
+use crate::segment::{Segment, SegmentId, TimeSpan, TranslationStatus};
+
+/// Version 2 added transcribe-only sessions and the `untranslated` status.
+pub const SESSION_FORMAT_VERSION: u32 = 2;
+
+/// The models that produced a session's text.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionModels {
+    pub transcriber: Option<String>,
+    pub translator: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionTranslationStatus {
+    Pending,
+    Done,
+    Failed,
+    Skipped,
+    Untranslated,
+}
+
+/// One segment as stored on disk. Times are in seconds from the start of the session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSegment {
+    pub id: u64,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// The language the translator thought the source was in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_language: Option<Language>,
+    pub status: SessionTranslationStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    /// Glossary terms the translation didn't use.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glossary_violations: Vec<GlossaryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub languages: LanguagePair,
+    #[serde(default)]
+    pub models: SessionModels,
+    /// Recorded without a translation model, so no segment was translated.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub transcribe_only: bool,
+    pub segments: Vec<SessionSegment>,
+}
+
+impl Session {
+    pub fn new(
+        languages: LanguagePair,
+        models: SessionModels,
+        transcribe_only: bool,
+        segments: &[Segment],
+    ) -> Self {
+        Self {
+            version: SESSION_FORMAT_VERSION,
+            languages,
+            models,
+            transcribe_only,
+            segments: segments.iter().map(SessionSegment::from).collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read session {}", path.display()))?;
+        let session: Session = serde_json::from_str(&json)
+            .with_context(|| format!("{} is not a valid session file", path.display()))?;
+        if session.version > SESSION_FORMAT_VERSION {
+            bail!(
+                "{} was saved by a newer transvibe (session format {}, this build reads up to {})",
+                path.display(),
+                session.version,
+                SESSION_FORMAT_VERSION
+            );
+        }
+        Ok(session)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Cannot write session {}", path.display()))
+    }
+
+    /// Converts the stored segments back for the UI, and makes sure new segments get fresh ids.
+    pub fn to_segments(&self) -> Vec<Segment> {
+        let segments: Vec<Segment> = self.segments.iter().map(Segment::from).collect();
+        if let Some(max_id) = segments.iter().map(|segment| segment.id).max() {
+            max_id.reserve_through();
+        }
+        segments
+    }
+
+    /// The languages to carry on in. Every segment is labelled with the session's one pair, so
+    /// asking for another would relabel what was already said; that is refused instead.
+    pub fn resume_languages(
+        &self,
+        source: Option<Language>,
+        target: Option<Language>,
+    ) -> Result<LanguagePair, anyhow::Error> {
+        for (flag, wanted, saved) in [
+            ("--source-lang", source, self.languages.source),
+            ("--target-lang", target, self.languages.target),
+        ] {
+            if let Some(wanted) = wanted
+                && wanted != saved
+            {
+                bail!(
+                    "The session is {} but {} asks for {}; leave out --resume to start a new session in other languages",
+                    self.languages,
+                    flag,
+                    wanted.code()
+                );
+            }
+        }
+        Ok(self.languages)
+    }
+
+    /// Where the session's clock stopped, so appended audio continues after it.
+    pub fn end_time(&self) -> Duration {
+        self.segments
+            .iter()
+            .filter_map(|segment| segment.end)
+            .fold(Duration::ZERO, |latest, end| {
+                latest.max(Duration::from_secs_f64(end.max(0.0)))
+            })
+    }
+}
+
+impl From<&Segment> for SessionSegment {
+    fn from(segment: &Segment) -> Self {
+        Self {
+            id: segment.id.0,
+            source: segment.source.clone(),
+            translation: (!segment.translation.is_empty()).then(|| segment.translation.clone()),
+            notes: segment.notes.clone(),
+            detected_language: segment.detected_language,
+            status: match segment.status {
+                TranslationStatus::Pending => SessionTranslationStatus::Pending,
+                TranslationStatus::Done => SessionTranslationStatus::Done,
+                TranslationStatus::Failed => SessionTranslationStatus::Failed,
+                TranslationStatus::Skipped => SessionTranslationStatus::Skipped,
+                TranslationStatus::Untranslated => SessionTranslationStatus::Untranslated,
+            },
+            start: segment.time.map(|time| time.start.as_secs_f64()),
+            end: segment.time.map(|time| time.end.as_secs_f64()),
+            glossary_violations: segment.glossary_violations.clone(),
+        }
+    }
+}
+
+impl From<&SessionSegment> for Segment {
+    fn from(stored: &SessionSegment) -> Self {
+        let mut segment = Segment::new(SegmentId(stored.id), stored.source.clone());
+        segment.translation = stored.translation.clone().unwrap_or_default();
+        segment.notes = stored.notes.clone();
+        segment.detected_language = stored.detected_language;
+        segment.glossary_violations = stored.glossary_violations.clone();
+        segment.status = match stored.status {
+            SessionTranslationStatus::Done => TranslationStatus::Done,
+            SessionTranslationStatus::Skipped => TranslationStatus::Skipped,
+            SessionTranslationStatus::Untranslated => TranslationStatus::Untranslated,
+            // Nothing is going to finish a translation from a previous run.
+            SessionTranslationStatus::Pending | SessionTranslationStatus::Failed => {
+                TranslationStatus::Failed
+            }
+        };
+        segment.time = match (stored.start, stored.end) {
+            (Some(start), Some(end)) => Some(TimeSpan {
+                start: Duration::from_secs_f64(start.max(0.0)),
+                end: Duration::from_secs_f64(end.max(start).max(0.0)),
+            }),
+            _ => None,
+        };
+        segment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(languages: LanguagePair) -> Session {
+        Session::new(languages, SessionModels::default(), false, &[])
+    }
+
+    #[test]
+    fn segments_survive_a_round_trip_through_json() {
+        let mut segment = Segment::new(SegmentId(7), "トランスバイブ".to_string());
+        segment.translation = "transvibe".to_string();
+        segment.status = TranslationStatus::Done;
+        segment.notes = Some("a product name".to_string());
+        segment.detected_language = Some(Language::JAPANESE);
+        segment.time = Some(TimeSpan {
+            start: Duration::from_millis(1_500),
+            end: Duration::from_millis(3_250),
+        });
+        segment.glossary_violations = vec![GlossaryEntry {
+            source: "トランスバイブ".to_string(),
+            target: "TransVibe".to_string(),
+        }];
+        let saved = Session::new(
+            LanguagePair::default(),
+            SessionModels::default(),
+            false,
+            std::slice::from_ref(&segment),
+        );
+
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded: Session = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, saved);
+        assert_eq!(loaded.to_segments(), [segment]);
+    }
+
+    #[test]
+    fn resuming_keeps_the_saved_languages() {
+        let korean: Language = "ko".parse().unwrap();
+        let saved = LanguagePair {
+            source: korean,
+            target: Language::ENGLISH,
+        };
+        let session = session(saved);
+        assert_eq!(session.resume_languages(None, None).unwrap(), saved);
+        // Repeating the saved languages is fine.
+        assert_eq!(
+            session
+                .resume_languages(Some(korean), Some(Language::ENGLISH))
+                .unwrap(),
+            saved
+        );
+    }
+
+    #[test]
+    fn resuming_in_other_languages_is_refused() {
+        let session = session(LanguagePair::default());
+        let spanish: Language = "es".parse().unwrap();
+        let error = session
+            .resume_languages(None, Some(spanish))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--target-lang"), "{error}");
+        assert!(
+            session
+                .resume_languages(Some(Language::ENGLISH), None)
+                .is_err()
+        );
+    }
+}
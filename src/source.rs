@@ -0,0 +1,348 @@
+//! Where the pipeline's audio comes from: capture devices, files, raw PCM, or a generated signal.
+//!
+//! Each [`AudioSource`] hands out an [`AsyncSource`] stream that feeds the same
+//! VAD → Whisper → translation chain, so the pipeline can run against any of them, including
+//! headless runs with [`SyntheticSource`] and the mock models.
+
+use anyhow::bail;
+use futures_util::future::Either;
+use futures_util::{Stream, stream};
+use kalosm::sound::rodio::Decoder;
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use kalosm::sound::rodio::source::UniformSourceIterator;
+use kalosm::sound::*;
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::app::AppUpdate;
+use crate::devices::{CaptureEndpoint, open_device};
+use crate::input::{AudioFile, open_audio_file};
+use crate::pcm::{PcmFormat, PcmStream};
+
+/// Something the pipeline can capture audio from.
+pub trait AudioSource {
+    type Stream: AsyncSource + Unpin;
+
+    /// Starts producing audio. Live sources are opened again on every resume; others are
+    /// opened once and just stop being read while paused.
+    fn open(&mut self) -> Result<Self::Stream, anyhow::Error>;
+
+    /// Whether the source keeps producing audio while nobody reads it, so pausing closes it.
+    fn is_live(&self) -> bool;
+
+    /// Short label for the status bar.
+    fn label(&self) -> String;
+
+    /// Length of the audio, if known up front.
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Opens another capture endpoint in place of the source's own. Only capture devices can.
+    fn open_endpoint(&mut self, endpoint: &CaptureEndpoint) -> Result<Self::Stream, anyhow::Error> {
+        bail!("{} cannot switch to {}", self.label(), endpoint)
+    }
+
+    /// Finds the speech in a stream this source opened, frame by frame. Kalosm's voice
+    /// activity detector, unless the source knows better.
+    fn detect_voice(
+        stream: Self::Stream,
+    ) -> impl Stream<Item = VoiceActivityDetectorOutput> + Unpin {
+        stream.voice_activity_stream()
+    }
+}
+
+/// A microphone, monitor or other capture endpoint; `None` for the default microphone.
+pub struct CaptureSource {
+    endpoint: Option<CaptureEndpoint>,
+    /// Told when a recorder process fails part way through.
+    tx: mpsc::Sender<AppUpdate>,
+}
+
+impl CaptureSource {
+    pub fn new(endpoint: Option<CaptureEndpoint>, tx: mpsc::Sender<AppUpdate>) -> Self {
+        Self { endpoint, tx }
+    }
+}
+
+/// Audio from the default microphone, a named capture device, or a PulseAudio/PipeWire source.
+pub enum CaptureStream {
+    Microphone(MicStream),
+    Device(PcmStream),
+    Pulse(PcmStream),
+}
+
+impl AsyncSource for CaptureStream {
+    fn as_stream(&mut self) -> impl Stream<Item = f32> + '_ {
+        match self {
+            CaptureStream::Microphone(stream) => Either::Left(stream.as_stream()),
+            CaptureStream::Device(stream) | CaptureStream::Pulse(stream) => {
+                Either::Right(stream.as_stream())
+            }
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            CaptureStream::Microphone(stream) => stream.sample_rate(),
+            CaptureStream::Device(stream) | CaptureStream::Pulse(stream) => stream.sample_rate(),
+        }
+    }
+}
+
+impl AudioSource for CaptureSource {
+    type Stream = CaptureStream;
+
+    fn open(&mut self) -> Result<CaptureStream, anyhow::Error> {
+        match self.endpoint.clone() {
+            Some(endpoint) => self.open_endpoint(&endpoint),
+            None => Ok(CaptureStream::Microphone(MicInput::default().stream())),
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn label(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => "Microphone".to_string(),
+        }
+    }
+
+    fn open_endpoint(
+        &mut self,
+        endpoint: &CaptureEndpoint,
+    ) -> Result<CaptureStream, anyhow::Error> {
+        Ok(match endpoint {
+            CaptureEndpoint::Device(name) => CaptureStream::Device(open_device(name)?),
+            CaptureEndpoint::PulseSource(name) => {
+                CaptureStream::Pulse(PcmStream::record_pulse_source(name, self.tx.clone())?)
+            }
+        })
+    }
+}
+
+/// A recording on disk, decoded as it is read.
+pub struct FileSource {
+    path: PathBuf,
+    /// Opened up front so unreadable files fail early; taken by the first `open`.
+    file: Option<AudioFile>,
+    total_duration: Option<Duration>,
+}
+
+impl FileSource {
+    pub fn open_path(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = open_audio_file(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            total_duration: file.total_duration,
+            file: Some(file),
+        })
+    }
+}
+
+impl AudioSource for FileSource {
+    type Stream = Decoder<BufReader<File>>;
+
+    fn open(&mut self) -> Result<Self::Stream, anyhow::Error> {
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => open_audio_file(&self.path)?,
+        };
+        Ok(file.decoder)
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn label(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+/// Raw interleaved PCM from a byte stream such as stdin. It can only be read once.
+pub struct PcmSource {
+    reader: Option<Box<dyn Read + Send>>,
+    format: PcmFormat,
+    label: String,
+}
+
+impl PcmSource {
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        format: PcmFormat,
+        label: impl Into<String>,
+    ) -> Self {
+        Self {
+            reader: Some(Box::new(reader)),
+            format,
+            label: label.into(),
+        }
+    }
+
+    pub fn stdin(format: PcmFormat) -> Self {
+        Self::new(std::io::stdin(), format, "stdin")
+    }
+}
+
+impl AudioSource for PcmSource {
+    type Stream = PcmStream;
+
+    fn open(&mut self) -> Result<PcmStream, anyhow::Error> {
+        let Some(reader) = self.reader.take() else {
+            bail!("{} has already been read", self.label);
+        };
+        Ok(PcmStream::from_reader(reader, self.format))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+}
+
+/// A generated mono signal built from tones, silence and speech clips, for exercising the
+/// pipeline without any audio hardware.
+#[derive(Debug, Clone)]
+pub struct SyntheticSource {
+    sample_rate: u32,
+    samples: Vec<f32>,
+    speech_level: Option<f32>,
+}
+
+impl SyntheticSource {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+            speech_level: None,
+        }
+    }
+
+    /// Appends a sine wave at `frequency` Hz.
+    pub fn tone(mut self, frequency: f32, duration: Duration) -> Self {
+        let rate = self.sample_rate as f32;
+        let count = self.sample_count(duration);
+        self.samples
+            .extend((0..count).map(|i| 0.5 * (TAU * frequency * i as f32 / rate).sin()));
+        self
+    }
+
+    pub fn silence(mut self, duration: Duration) -> Self {
+        let count = self.sample_count(duration);
+        self.samples.extend(std::iter::repeat_n(0.0, count));
+        self
+    }
+
+    /// Appends mono samples already at this source's sample rate.
+    pub fn clip(mut self, samples: impl IntoIterator<Item = f32>) -> Self {
+        self.samples.extend(samples);
+        self
+    }
+
+    /// Appends a recorded clip, such as a spoken line, converted to this source's format.
+    pub fn clip_file(self, path: &Path) -> Result<Self, anyhow::Error> {
+        let file = open_audio_file(path)?;
+        let rate = self.sample_rate;
+        Ok(self.clip(UniformSourceIterator::<_, f32>::new(file.decoder, 1, rate)))
+    }
+
+    /// Counts every frame louder than `rms` as speech, instead of running the voice activity
+    /// detector, which hears tones as the noise they are. For tests of the pipeline's timing.
+    pub fn with_speech_level(mut self, rms: f32) -> Self {
+        self.speech_level = Some(rms);
+        self
+    }
+
+    fn sample_count(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * f64::from(self.sample_rate)).round() as usize
+    }
+}
+
+/// The audio of a [`SyntheticSource`].
+pub struct SyntheticStream {
+    sample_rate: u32,
+    samples: Vec<f32>,
+    speech_level: Option<f32>,
+}
+
+impl AsyncSource for SyntheticStream {
+    fn as_stream(&mut self) -> impl Stream<Item = f32> + '_ {
+        stream::iter(self.samples.iter().copied())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// Frames a [`SyntheticSource`] with a speech level is split into, as long as the detector's.
+const SYNTHETIC_FRAME: Duration = Duration::from_millis(30);
+
+impl AudioSource for SyntheticSource {
+    type Stream = SyntheticStream;
+
+    fn open(&mut self) -> Result<Self::Stream, anyhow::Error> {
+        Ok(SyntheticStream {
+            sample_rate: self.sample_rate,
+            samples: self.samples.clone(),
+            speech_level: self.speech_level,
+        })
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn label(&self) -> String {
+        "Test signal".to_string()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(
+            self.samples.len() as f64 / f64::from(self.sample_rate),
+        ))
+    }
+
+    fn detect_voice(
+        stream: SyntheticStream,
+    ) -> impl Stream<Item = VoiceActivityDetectorOutput> + Unpin {
+        let Some(level) = stream.speech_level else {
+            return Either::Left(stream.voice_activity_stream());
+        };
+        let frame_size =
+            (SYNTHETIC_FRAME.as_secs_f64() * f64::from(stream.sample_rate)).round() as usize;
+        let frames: Vec<_> = stream
+            .samples
+            .chunks(frame_size.max(1))
+            .map(|frame| {
+                let rms = (frame.iter().map(|sample| sample * sample).sum::<f32>()
+                    / frame.len() as f32)
+                    .sqrt();
+                VoiceActivityDetectorOutput {
+                    probability: if rms > level { 1.0 } else { 0.0 },
+                    samples: SamplesBuffer::new(1, stream.sample_rate, frame.to_vec()),
+                }
+            })
+            .collect();
+        Either::Right(stream::iter(frames))
+    }
+}
@@ -0,0 +1,73 @@
+use futures_util::{Stream, StreamExt, future};
+use kalosm::language::ModelLoadingProgress;
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use kalosm::sound::*;
+use kalosm_common::Cache;
+use std::path::Path;
+
+use crate::language::Language;
+use crate::models::WhisperModel;
+
+/// Turns a chunk of speech audio into text.
+pub trait Transcriber: Send + Sync + 'static {
+    /// Transcribes one voice-activity chunk, yielding the text of each recognized
+    /// segment as soon as it is available.
+    fn transcribe(
+        &self,
+        audio: SamplesBuffer<f32>,
+    ) -> impl Stream<Item = String> + Send + Unpin + 'static;
+
+    /// A short description of the model, recorded in saved sessions.
+    fn model_name(&self) -> String;
+}
+
+/// Segments Whisper is this sure contain no speech are dropped.
+const NO_SPEECH_THRESHOLD: f64 = 0.85;
+
+/// The default [`Transcriber`], backed by a local Whisper model.
+pub struct WhisperTranscriber {
+    model: Whisper,
+    name: WhisperModel,
+}
+
+impl WhisperTranscriber {
+    /// Downloads (if needed) and loads a Whisper model, fixed to `language`.
+    /// With a `model_dir`, the model is read from there instead of kalosm's cache.
+    /// `on_progress` hears about the download and load as they go.
+    pub async fn load(
+        language: Language,
+        name: WhisperModel,
+        model_dir: Option<&Path>,
+        on_progress: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = WhisperBuilder::default()
+            .with_source(name.source())
+            .with_language(Some(language.whisper_language()));
+        // kalosm's Whisper builder only takes a named source, not file paths, so the directory
+        // stands in for its cache. `ModelSettings::resolve` has already checked that every file
+        // the source names is there, and with no network the cache uses them as they are.
+        if let Some(dir) = model_dir {
+            builder = builder.with_cache(Cache::new(dir.to_path_buf()));
+        }
+        let model = builder.build_with_loading_handler(on_progress).await?;
+        Ok(Self { model, name })
+    }
+}
+
+impl Transcriber for WhisperTranscriber {
+    fn transcribe(
+        &self,
+        audio: SamplesBuffer<f32>,
+    ) -> impl Stream<Item = String> + Send + Unpin + 'static {
+        self.model.transcribe(audio).filter_map(|transcribed| {
+            future::ready(
+                (transcribed.probability_of_no_speech() < NO_SPEECH_THRESHOLD)
+                    .then(|| transcribed.text().to_string()),
+            )
+        })
+    }
+
+    fn model_name(&self) -> String {
+        format!("whisper {}", self.name)
+    }
+}
@@ -0,0 +1,559 @@
+use anyhow::anyhow;
+use kalosm::language::kalosm_sample;
+use kalosm::language::*;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+
+use crate::glossary::Glossary;
+use crate::language::{Language, LanguagePair};
+use crate::models::LlmSource;
+
+/// Times a translation is generated before giving up, when the output doesn't pass [`check_output`].
+pub const MAX_TRANSLATION_ATTEMPTS: usize = 3;
+
+/// The system prompt the translation model runs under.
+pub fn system_prompt(languages: LanguagePair) -> String {
+    format!(
+        "You are an expert translator. Translate the given {source} text to {target} accurately and concisely. Respond with JSON in this format: {schema}\n\"translation\" holds only the {target} translation, with no pleasantries or explanations. Put anything else worth saying in \"notes\", or null. \"detected_language\" is the ISO 639-1 code of the language the text is actually in.",
+        source = languages.source,
+        target = languages.target,
+        schema = TranslationOutput::schema(),
+    )
+}
+
+/// The system prompt batches of lines are translated under, one output entry per input line.
+pub fn batch_system_prompt(languages: LanguagePair) -> String {
+    format!(
+        "You are an expert translator. Translate each of the given {source} lines to {target} accurately and concisely. Respond with JSON in this format: {schema}\n\"lines\" holds exactly one {target} translation per input line, in the same order, with no pleasantries or explanations. Never merge, split, skip or add lines.",
+        source = languages.source,
+        target = languages.target,
+        schema = BatchTranslationOutput::schema(),
+    )
+}
+
+/// The per-segment request sent to the translation model.
+///
+/// `context` holds the segments just before this one, oldest first, so that pronouns and
+/// omitted subjects can be resolved. It is shown to the model but not translated again.
+/// Only the glossary terms that occur in `text` are included.
+pub fn translation_prompt(
+    languages: LanguagePair,
+    glossary: &Glossary,
+    context: &[ContextPair],
+    text: &str,
+) -> String {
+    let mut prompt = prompt_preamble(glossary, context, text);
+    prompt.push_str(&format!(
+        "Translate the following {source} text to {target}. Do not translate {target}, keep as is.:\n{text}",
+        source = languages.source,
+        target = languages.target,
+    ));
+    prompt
+}
+
+/// The request for a batch of lines, sent under [`batch_system_prompt`].
+///
+/// The lines go in as a JSON array so the model can tell exactly where each one ends.
+/// Context and glossary terms are included as in [`translation_prompt`].
+pub fn batch_translation_prompt(
+    languages: LanguagePair,
+    glossary: &Glossary,
+    context: &[ContextPair],
+    lines: &[String],
+) -> String {
+    let mut prompt = prompt_preamble(glossary, context, &lines.join("\n"));
+    prompt.push_str(&format!(
+        "Translate each of the following {count} {source} lines to {target}, giving {count} entries in \"lines\". Do not translate {target}, keep as is.:\n{lines}",
+        count = lines.len(),
+        source = languages.source,
+        target = languages.target,
+        lines = serde_json::to_string(lines).unwrap_or_default(),
+    ));
+    prompt
+}
+
+/// The glossary terms used in `text` and the earlier conversation, ahead of the request itself.
+fn prompt_preamble(glossary: &Glossary, context: &[ContextPair], text: &str) -> String {
+    let mut prompt = String::new();
+    let mut terms = glossary.matching(text).peekable();
+    if terms.peek().is_some() {
+        prompt.push_str("Always translate these terms exactly as given:\n");
+        for entry in terms {
+            prompt.push_str(&format!("{}\n", entry));
+        }
+        prompt.push('\n');
+    }
+    if !context.is_empty() {
+        prompt
+            .push_str("For context, the conversation so far was (do not translate this again):\n");
+        for pair in context {
+            prompt.push_str(&format!("{}\n=> {}\n", pair.source, pair.translation));
+        }
+        prompt.push('\n');
+    }
+    prompt
+}
+
+/// A finished, checked translation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Translation {
+    /// Empty when the model produced nothing usable.
+    pub text: String,
+    /// Anything the model had to say about the translation, kept out of the text itself.
+    pub notes: Option<String>,
+    /// The language the model thinks the source text is actually in.
+    pub detected_language: Option<Language>,
+}
+
+impl Translation {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+}
+
+/// What the model is constrained to generate.
+#[derive(Parse, Schema, Clone, Debug)]
+struct TranslationOutput {
+    translation: String,
+    notes: Option<String>,
+    #[parse(with = StringParser::new(2..=3))]
+    detected_language: String,
+}
+
+/// Rejects output that parsed but still isn't a usable translation.
+fn check_output(output: TranslationOutput) -> Result<Translation, String> {
+    let text = clean_model_output(&output.translation);
+    if text.is_empty() {
+        return Err("empty translation".to_string());
+    }
+    if text.contains("<|") {
+        return Err(format!("chat markup in translation: {text}"));
+    }
+    let notes = output
+        .notes
+        .map(|notes| clean_model_output(&notes))
+        .filter(|notes| !notes.is_empty());
+    Ok(Translation {
+        text,
+        notes,
+        // The model's guess is only advisory, so an unknown code isn't worth a retry.
+        detected_language: output.detected_language.parse().ok(),
+    })
+}
+
+/// What the model is constrained to generate for a batch: one entry per input line.
+#[derive(Parse, Schema, Clone, Debug)]
+struct BatchTranslationOutput {
+    lines: Vec<String>,
+}
+
+/// Rejects batch output that doesn't line up with the `expected` input lines.
+fn check_batch_output(
+    output: BatchTranslationOutput,
+    expected: usize,
+) -> Result<Vec<String>, String> {
+    if output.lines.len() != expected {
+        return Err(format!(
+            "{} lines translated out of {}",
+            output.lines.len(),
+            expected
+        ));
+    }
+    let lines: Vec<String> = output
+        .lines
+        .iter()
+        .map(|line| clean_model_output(line))
+        .collect();
+    if let Some(index) = lines.iter().position(String::is_empty) {
+        return Err(format!("line {} of the batch is empty", index + 1));
+    }
+    if let Some(line) = lines.iter().find(|line| line.contains("<|")) {
+        return Err(format!("chat markup in translation: {line}"));
+    }
+    Ok(lines)
+}
+
+/// The `"translation"` field of a partially generated [`TranslationOutput`], so far.
+fn partial_translation(raw: &str) -> Option<String> {
+    let key = raw.find("\"translation\"")? + "\"translation\"".len();
+    let value = raw[key..].trim_start().strip_prefix(':')?;
+    let mut chars = value.trim_start().strip_prefix('"')?.chars();
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(escaped) => text.push(escaped),
+                None => break,
+            },
+            c => text.push(c),
+        }
+    }
+    Some(text)
+}
+
+/// A segment translated earlier in the session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextPair {
+    pub source: String,
+    pub translation: String,
+}
+
+/// How much earlier conversation goes along with each translation request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextConfig {
+    /// At most this many previous segments. Zero turns context off.
+    pub max_pairs: usize,
+    /// At most roughly this many tokens of them, dropping the oldest first.
+    pub token_budget: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_pairs: 3,
+            token_budget: 512,
+        }
+    }
+}
+
+/// The most recent translated segments, trimmed to a [`ContextConfig`].
+#[derive(Debug, Clone, Default)]
+pub struct ContextWindow {
+    config: ContextConfig,
+    pairs: VecDeque<ContextPair>,
+}
+
+impl ContextWindow {
+    pub fn new(config: ContextConfig) -> Self {
+        Self {
+            config,
+            pairs: VecDeque::new(),
+        }
+    }
+
+    /// Remembers a finished translation, forgetting the oldest once the window is full.
+    pub fn push(&mut self, pair: ContextPair) {
+        if self.config.max_pairs == 0 {
+            return;
+        }
+        self.pairs.push_back(pair);
+        while self.pairs.len() > self.config.max_pairs {
+            self.pairs.pop_front();
+        }
+    }
+
+    /// The newest pairs that fit in the token budget, oldest first.
+    pub fn recent(&self) -> Vec<ContextPair> {
+        let mut budget = self.config.token_budget;
+        let mut recent: Vec<ContextPair> = self
+            .pairs
+            .iter()
+            .rev()
+            .map_while(|pair| {
+                let cost = estimate_tokens(&pair.source) + estimate_tokens(&pair.translation);
+                budget = budget.checked_sub(cost)?;
+                Some(pair.clone())
+            })
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+/// A rough token count that needs no tokenizer: one per CJK character, one per four others.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (wide, narrow) = text.chars().fold((0usize, 0usize), |(wide, narrow), c| {
+        if c as u32 >= 0x2E80 {
+            (wide + 1, narrow)
+        } else {
+            (wide, narrow + 1)
+        }
+    });
+    wide + narrow.div_ceil(4)
+}
+
+/// Turns a finished source-language segment into the target language.
+pub trait Translator: Send + Sync + 'static {
+    /// Translates one segment, with the segments before it as `context` (oldest first).
+    /// An empty result means the model produced nothing usable.
+    ///
+    /// `on_partial` is called with the text generated so far each time it grows, so it can be
+    /// shown before generation finishes. The returned text replaces it.
+    fn translate(
+        &self,
+        text: &str,
+        context: &[ContextPair],
+        on_partial: impl FnMut(&str) + Send,
+    ) -> impl Future<Output = Result<Translation, anyhow::Error>> + Send;
+
+    /// Translates several lines in one request, with `context` as for [`Translator::translate`].
+    /// On success there is exactly one non-empty translation per line, in the same order.
+    fn translate_lines(
+        &self,
+        lines: &[String],
+        context: &[ContextPair],
+    ) -> impl Future<Output = Result<Vec<String>, anyhow::Error>> + Send;
+
+    /// A short description of the model, recorded in saved sessions.
+    fn model_name(&self) -> String;
+}
+
+/// The default [`Translator`], backed by a local chat model (Qwen 2.5 unless configured otherwise).
+pub struct LlamaTranslator {
+    chat_template: Chat<Llama>,
+    batch_template: Chat<Llama>,
+    languages: LanguagePair,
+    glossary: Glossary,
+    source: LlmSource,
+}
+
+impl LlamaTranslator {
+    /// Downloads (if needed) and loads a translation model.
+    /// With a `model_dir`, the model is read from there instead of kalosm's cache.
+    /// `on_progress` hears about the download and load as they go.
+    pub async fn load(
+        languages: LanguagePair,
+        glossary: Glossary,
+        source: LlmSource,
+        model_dir: Option<&Path>,
+        on_progress: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> Result<Self, anyhow::Error> {
+        let llama_model = Llama::builder()
+            .with_source(source.llama_source(model_dir))
+            .build_with_loading_handler(on_progress)
+            .await?;
+        let chat_template = llama_model
+            .chat()
+            .with_system_prompt(system_prompt(languages));
+        let batch_template = llama_model
+            .chat()
+            .with_system_prompt(batch_system_prompt(languages));
+        Ok(Self {
+            chat_template,
+            batch_template,
+            languages,
+            glossary,
+            source,
+        })
+    }
+}
+
+impl Translator for LlamaTranslator {
+    async fn translate(
+        &self,
+        text: &str,
+        context: &[ContextPair],
+        mut on_partial: impl FnMut(&str) + Send,
+    ) -> Result<Translation, anyhow::Error> {
+        let prompt = translation_prompt(self.languages, &self.glossary, context, text);
+
+        let mut last_error = String::new();
+        for _ in 0..MAX_TRANSLATION_ATTEMPTS {
+            // Each attempt starts from a fresh copy of the template so segments don't share history.
+            let mut llama_chat = self.chat_template.clone();
+            let mut response_stream = llama_chat(&prompt).typed::<TranslationOutput>();
+            let mut raw_output = String::new();
+            while let Some(token) = response_stream.next().await {
+                raw_output.push_str(&token);
+                if let Some(partial) = partial_translation(&raw_output) {
+                    on_partial(&clean_model_output(&partial));
+                }
+            }
+            match response_stream.await {
+                Ok(output) => match check_output(output) {
+                    Ok(translation) => return Ok(translation),
+                    Err(e) => last_error = e,
+                },
+                Err(e) => last_error = e.to_string(),
+            }
+            on_partial("");
+        }
+        Err(anyhow!(
+            "no usable translation after {} attempts: {}",
+            MAX_TRANSLATION_ATTEMPTS,
+            last_error
+        ))
+    }
+
+    async fn translate_lines(
+        &self,
+        lines: &[String],
+        context: &[ContextPair],
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let prompt = batch_translation_prompt(self.languages, &self.glossary, context, lines);
+
+        let mut last_error = String::new();
+        for _ in 0..MAX_TRANSLATION_ATTEMPTS {
+            let mut llama_chat = self.batch_template.clone();
+            match llama_chat(&prompt).typed::<BatchTranslationOutput>().await {
+                Ok(output) => match check_batch_output(output, lines.len()) {
+                    Ok(translations) => return Ok(translations),
+                    Err(e) => last_error = e,
+                },
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(anyhow!(
+            "no usable batch translation after {} attempts: {}",
+            MAX_TRANSLATION_ATTEMPTS,
+            last_error
+        ))
+    }
+
+    fn model_name(&self) -> String {
+        self.source.to_string()
+    }
+}
+
+/// Strips chat-template markers that occasionally leak into the generated text.
+pub fn clean_model_output(raw: &str) -> String {
+    raw.replace("<|im_start|>", "")
+        .replace("<|im_end|>", "")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(source: &str, translation: &str) -> ContextPair {
+        ContextPair {
+            source: source.to_string(),
+            translation: translation.to_string(),
+        }
+    }
+
+    fn sources(pairs: &[ContextPair]) -> Vec<&str> {
+        pairs.iter().map(|pair| pair.source.as_str()).collect()
+    }
+
+    #[test]
+    fn tokens_are_estimated_per_cjk_character_and_per_four_other_characters() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("こんにちは"), 5);
+        assert_eq!(estimate_tokens("会議 at 3"), 2 + 2);
+    }
+
+    #[test]
+    fn the_window_keeps_the_newest_pairs_oldest_first() {
+        let mut window = ContextWindow::new(ContextConfig {
+            max_pairs: 2,
+            token_budget: 1_000,
+        });
+        assert!(window.recent().is_empty());
+        for source in ["一", "二", "三"] {
+            window.push(pair(source, "x"));
+        }
+        assert_eq!(sources(&window.recent()), ["二", "三"]);
+    }
+
+    #[test]
+    fn the_token_budget_leaves_out_the_oldest_pairs_first() {
+        let mut window = ContextWindow::new(ContextConfig {
+            max_pairs: 10,
+            token_budget: 5,
+        });
+        // Two tokens each: one CJK character and up to four other characters.
+        for source in ["一", "二", "三"] {
+            window.push(pair(source, "abcd"));
+        }
+        assert_eq!(sources(&window.recent()), ["二", "三"]);
+    }
+
+    #[test]
+    fn a_pair_over_the_budget_cuts_off_everything_before_it() {
+        let mut window = ContextWindow::new(ContextConfig {
+            max_pairs: 10,
+            token_budget: 4,
+        });
+        window.push(pair("一", "a"));
+        window.push(pair("長い長い文章", "a long sentence"));
+        window.push(pair("三", "c"));
+        assert_eq!(sources(&window.recent()), ["三"]);
+    }
+
+    #[test]
+    fn zero_pairs_turns_context_off() {
+        let mut window = ContextWindow::new(ContextConfig {
+            max_pairs: 0,
+            token_budget: 1_000,
+        });
+        window.push(pair("一", "one"));
+        assert!(window.recent().is_empty());
+    }
+
+    #[test]
+    fn the_partial_translation_is_read_however_the_json_is_spaced() {
+        for raw in [
+            r#"{"translation": "Hello, \"wor"#,
+            r#"{"translation":"Hello, \"wor"#,
+            "{\n  \"translation\" :\n  \"Hello, \\\"wor",
+        ] {
+            assert_eq!(
+                partial_translation(raw).as_deref(),
+                Some("Hello, \"wor"),
+                "{raw}"
+            );
+        }
+        assert_eq!(
+            partial_translation(r#"{"translation": "a\nb", "notes""#).as_deref(),
+            Some("a\nb")
+        );
+    }
+
+    #[test]
+    fn there_is_no_partial_translation_before_its_value_starts() {
+        for raw in [
+            "",
+            r#"{"transl"#,
+            r#"{"translation""#,
+            r#"{"translation": "#,
+        ] {
+            assert_eq!(partial_translation(raw), None, "{raw}");
+        }
+    }
+
+    fn batch(lines: &[&str]) -> BatchTranslationOutput {
+        BatchTranslationOutput {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn batch_output_must_match_the_input_line_for_line() {
+        assert_eq!(
+            check_batch_output(batch(&["One", " Two<|im_end|>"]), 2),
+            Ok(vec!["One".to_string(), "Two".to_string()])
+        );
+        assert_eq!(
+            check_batch_output(batch(&["One two"]), 2),
+            Err("1 lines translated out of 2".to_string())
+        );
+        assert_eq!(
+            check_batch_output(batch(&["One", " "]), 2),
+            Err("line 2 of the batch is empty".to_string())
+        );
+    }
+
+    #[test]
+    fn the_batch_prompt_lists_every_line_in_order() {
+        let languages = LanguagePair::default();
+        let lines = vec!["一行目".to_string(), "二行目 \"引用\"".to_string()];
+        let prompt = batch_translation_prompt(languages, &Glossary::default(), &[], &lines);
+        assert!(prompt.contains("following 2 "), "{prompt}");
+        assert!(
+            prompt.ends_with(r#"["一行目","二行目 \"引用\""]"#),
+            "{prompt}"
+        );
+    }
+}
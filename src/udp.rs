@@ -0,0 +1,466 @@
+//! Live audio received over UDP, as bare PCM datagrams or as RTP packets with L16 payloads.
+//!
+//! RTP packets are put back in order by a small jitter buffer, and packets that never show up
+//! are replaced with silence so the input's clock keeps pace with the sender's.
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::app::AppUpdate;
+use crate::pcm::{PcmDecoder, PcmFormat, PcmStream};
+use crate::source::AudioSource;
+
+/// How long to wait for a packet before letting out whatever the jitter buffer holds.
+const IDLE_FLUSH: Duration = Duration::from_millis(100);
+/// A jump in sequence numbers bigger than this means the sender restarted, not packet loss.
+const MAX_GAP: u64 = 500;
+
+/// How the datagrams carry audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpConfig {
+    /// For RTP, the sample format is L16's big-endian 16-bit.
+    pub format: PcmFormat,
+    /// Datagrams are RTP packets rather than bare PCM.
+    pub rtp: bool,
+    /// RTP packets that may arrive after a missing one before it is given up as lost.
+    pub jitter_packets: usize,
+}
+
+/// How well packets are arriving, as shown in the status line.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StreamHealth {
+    /// Datagrams received over the last second or so.
+    pub packets_per_sec: f64,
+    /// Packets played out since the stream (re)opened.
+    pub delivered: u64,
+    /// Packets that never arrived in time, replaced with silence.
+    pub lost: u64,
+    /// Only RTP has sequence numbers to notice loss by.
+    pub tracks_loss: bool,
+}
+
+impl StreamHealth {
+    pub fn loss_ratio(&self) -> f64 {
+        match self.delivered + self.lost {
+            0 => 0.0,
+            expected => self.lost as f64 / expected as f64,
+        }
+    }
+}
+
+impl fmt::Display for StreamHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0} pkt/s", self.packets_per_sec)?;
+        if self.tracks_loss {
+            write!(f, ", {:.1}% loss", self.loss_ratio() * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// A UDP port audio is sent to. Packets that arrive while paused are discarded.
+pub struct UdpSource {
+    socket: UdpSocket,
+    config: UdpConfig,
+    tx: mpsc::Sender<AppUpdate>,
+    /// The thread receiving for the stream opened last, and the flag that stops it.
+    receiver: Option<(JoinHandle<()>, Arc<AtomicBool>)>,
+}
+
+impl UdpSource {
+    /// Starts listening on `addr`. Stream health is reported to `tx` while the stream is open.
+    pub fn bind(
+        addr: SocketAddr,
+        config: UdpConfig,
+        tx: mpsc::Sender<AppUpdate>,
+    ) -> Result<Self, anyhow::Error> {
+        let socket =
+            UdpSocket::bind(addr).with_context(|| format!("Cannot listen on UDP {addr}"))?;
+        Ok(Self {
+            socket,
+            config,
+            tx,
+            receiver: None,
+        })
+    }
+}
+
+impl AudioSource for UdpSource {
+    type Stream = PcmStream;
+
+    fn open(&mut self) -> Result<PcmStream, anyhow::Error> {
+        // The last stream's thread may still be waiting on the socket, and would take packets
+        // meant for the new one, so it is stopped first. It notices within `IDLE_FLUSH`.
+        if let Some((thread, stop)) = self.receiver.take() {
+            stop.store(true, Ordering::Relaxed);
+            thread.join().ok();
+        }
+        let socket = self
+            .socket
+            .try_clone()
+            .context("Cannot read the UDP socket")?;
+        discard_queued(&socket)?;
+        socket.set_read_timeout(Some(IDLE_FLUSH))?;
+        let (samples_tx, chunks) = mpsc::channel(64);
+        let config = self.config;
+        let tx = self.tx.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || receive(socket, config, stopped, samples_tx, tx));
+        self.receiver = Some((thread, stop));
+        Ok(PcmStream::from_channel(chunks))
+    }
+
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn label(&self) -> String {
+        let protocol = if self.config.rtp { "RTP" } else { "UDP" };
+        match self.socket.local_addr() {
+            Ok(addr) => format!("{protocol} {addr}"),
+            Err(_) => protocol.to_string(),
+        }
+    }
+}
+
+/// Drops datagrams that queued up while nobody was reading, e.g. during a pause.
+fn discard_queued(socket: &UdpSocket) -> Result<(), anyhow::Error> {
+    let mut buffer = [0; 2048];
+    socket.set_nonblocking(true)?;
+    while socket.recv(&mut buffer).is_ok() {}
+    socket.set_nonblocking(false)?;
+    Ok(())
+}
+
+/// Receives until the stream is dropped or `stop` is set, sending decoded audio to `samples`.
+fn receive(
+    socket: UdpSocket,
+    config: UdpConfig,
+    stop: Arc<AtomicBool>,
+    samples: mpsc::Sender<Vec<f32>>,
+    tx: mpsc::Sender<AppUpdate>,
+) {
+    let mut decoder = PcmDecoder::new(config.format);
+    let mut jitter = JitterBuffer::new(config.jitter_packets);
+    let mut meter = HealthMeter::new(config.rtp);
+    let mut buffer = vec![0; 65_536];
+    let mut released = Vec::new();
+    // Lost packets are filled with as much silence as the last packet held.
+    let mut packet_samples = 0;
+    while !samples.is_closed() && !stop.load(Ordering::Relaxed) {
+        match socket.recv(&mut buffer) {
+            Ok(len) => {
+                meter.received += 1;
+                let packet = &buffer[..len];
+                if !config.rtp {
+                    released.push(Released::Packet(packet.to_vec()));
+                } else if let Some((sequence, payload)) = parse_rtp(packet) {
+                    jitter.push(sequence, payload.to_vec());
+                    jitter.release(&mut released);
+                }
+            }
+            // Nothing for a while: the sender may have paused, so don't hold back what we have.
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                jitter.flush(&mut released);
+            }
+            Err(_) => break,
+        }
+
+        let mut audio = Vec::new();
+        for packet in released.drain(..) {
+            match packet {
+                Released::Packet(payload) => {
+                    let decoded = decoder.decode(&payload);
+                    packet_samples = decoded.len();
+                    audio.extend(decoded);
+                    meter.health.delivered += 1;
+                }
+                Released::Lost => {
+                    audio.extend(std::iter::repeat_n(0.0, packet_samples));
+                    meter.health.lost += 1;
+                }
+            }
+        }
+        if !audio.is_empty() && samples.blocking_send(audio).is_err() {
+            break;
+        }
+        if let Some(health) = meter.report() {
+            // Dropping a report is fine, another follows in a second.
+            tx.try_send(AppUpdate::StreamHealth(health)).ok();
+        }
+    }
+}
+
+/// Counts packets and works out the rate about once a second.
+struct HealthMeter {
+    health: StreamHealth,
+    /// Datagrams received since `since`.
+    received: u64,
+    since: Instant,
+}
+
+impl HealthMeter {
+    fn new(tracks_loss: bool) -> Self {
+        Self {
+            health: StreamHealth {
+                tracks_loss,
+                ..StreamHealth::default()
+            },
+            received: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn report(&mut self) -> Option<StreamHealth> {
+        let elapsed = self.since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        self.health.packets_per_sec = self.received as f64 / elapsed.as_secs_f64();
+        self.received = 0;
+        self.since = Instant::now();
+        Some(self.health)
+    }
+}
+
+/// What the jitter buffer lets out, in sequence order.
+enum Released {
+    Packet(Vec<u8>),
+    Lost,
+}
+
+/// Puts RTP packets back in sequence order. A missing packet is waited for until `depth`
+/// later packets have arrived, then given up as lost.
+struct JitterBuffer {
+    depth: usize,
+    /// Extended sequence number of the next packet to let out, once the stream has started.
+    next: Option<u64>,
+    packets: BTreeMap<u64, Vec<u8>>,
+}
+
+impl JitterBuffer {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            next: None,
+            packets: BTreeMap::new(),
+        }
+    }
+
+    fn push(&mut self, sequence: u16, payload: Vec<u8>) {
+        let reference = self
+            .next
+            .or_else(|| self.packets.last_key_value().map(|(&key, _)| key));
+        let extended = extend_sequence(sequence, reference);
+        if let Some(next) = self.next
+            && extended < next
+        {
+            if next - extended <= MAX_GAP {
+                return; // Too late: it has already been given up as lost.
+            }
+            // The sender started over; so do we.
+            self.next = None;
+            self.packets.clear();
+        }
+        self.packets.entry(extended).or_insert(payload);
+    }
+
+    /// Lets out packets that are next in line, and gives up on missing ones once too many
+    /// packets are waiting behind them.
+    fn release(&mut self, out: &mut Vec<Released>) {
+        while let Some((&first, _)) = self.packets.first_key_value() {
+            let next = *self.next.get_or_insert(first);
+            if first - next > MAX_GAP {
+                self.next = Some(first);
+            } else if first == next {
+                out.extend(self.packets.remove(&first).map(Released::Packet));
+                self.next = Some(next + 1);
+            } else if self.packets.len() > self.depth {
+                out.push(Released::Lost);
+                self.next = Some(next + 1);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Lets out everything held, counting any gaps as lost.
+    fn flush(&mut self, out: &mut Vec<Released>) {
+        let depth = std::mem::replace(&mut self.depth, 0);
+        self.release(out);
+        self.depth = depth;
+    }
+}
+
+/// Widens a 16-bit RTP sequence number to the one nearest `reference`, so wrapping around
+/// keeps counting up. Numbers start at 65536 so the first packets can still wrap backwards.
+fn extend_sequence(sequence: u16, reference: Option<u64>) -> u64 {
+    let Some(reference) = reference else {
+        return 0x1_0000 + u64::from(sequence);
+    };
+    let candidate = (reference & !0xFFFF) | u64::from(sequence);
+    if candidate + 0x8000 < reference {
+        candidate + 0x1_0000
+    } else if candidate > reference + 0x8000 {
+        candidate.saturating_sub(0x1_0000)
+    } else {
+        candidate
+    }
+}
+
+/// The sequence number and payload of an RTP packet, or `None` if it isn't one.
+fn parse_rtp(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+    let csrc_count = usize::from(packet[0] & 0x0F);
+    let mut start = 12 + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        // A header extension: 4 bytes, then its length in 32-bit words.
+        let words = u16::from_be_bytes([*packet.get(start + 2)?, *packet.get(start + 3)?]);
+        start += 4 + 4 * usize::from(words);
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        // Padding; its last byte says how much.
+        end = end.checked_sub(usize::from(packet[end - 1]))?;
+    }
+    let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+    Some((sequence, packet.get(start..end)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pushes packets whose payload is their sequence number's low byte, and lets out what
+    /// the buffer will. `None` stands for a packet given up as lost.
+    fn push_all(jitter: &mut JitterBuffer, sequences: &[u16]) -> Vec<Option<u8>> {
+        let mut out = Vec::new();
+        for &sequence in sequences {
+            jitter.push(sequence, vec![sequence as u8]);
+            jitter.release(&mut out);
+        }
+        payloads(out)
+    }
+
+    fn payloads(released: Vec<Released>) -> Vec<Option<u8>> {
+        released
+            .into_iter()
+            .map(|packet| match packet {
+                Released::Packet(payload) => Some(payload[0]),
+                Released::Lost => None,
+            })
+            .collect()
+    }
+
+    /// An RTP header with version 2, the given flags and `sequence`, and no CSRCs.
+    fn rtp_header(flags: u8, sequence: u16) -> Vec<u8> {
+        let mut header = vec![0x80 | flags, 96];
+        header.extend(sequence.to_be_bytes());
+        header.extend([0; 8]); // Timestamp and SSRC.
+        header
+    }
+
+    #[test]
+    fn sequence_numbers_keep_counting_across_the_wrap() {
+        assert_eq!(extend_sequence(7, None), 0x1_0007);
+        assert_eq!(extend_sequence(3, Some(0x1_0005)), 0x1_0003);
+        assert_eq!(extend_sequence(0, Some(0x1_FFFF)), 0x2_0000);
+        // A late packet from before the wrap goes back below it.
+        assert_eq!(extend_sequence(0xFFFF, Some(0x2_0001)), 0x1_FFFF);
+        assert_eq!(extend_sequence(0xFFFF, Some(0x1_0000)), 0xFFFF);
+    }
+
+    #[test]
+    fn packets_out_of_order_are_let_out_in_order() {
+        let mut jitter = JitterBuffer::new(2);
+        assert_eq!(
+            push_all(&mut jitter, &[1, 3, 2, 4]),
+            [Some(1), Some(2), Some(3), Some(4)]
+        );
+        // Too late to be let out again.
+        assert!(push_all(&mut jitter, &[2]).is_empty());
+    }
+
+    #[test]
+    fn the_wraparound_is_just_the_next_packet() {
+        let mut jitter = JitterBuffer::new(2);
+        assert_eq!(
+            push_all(&mut jitter, &[0xFFFE, 0, 0xFFFF, 1]),
+            [Some(0xFE), Some(0xFF), Some(0), Some(1)]
+        );
+    }
+
+    #[test]
+    fn a_missing_packet_is_lost_once_enough_have_arrived_behind_it() {
+        let mut jitter = JitterBuffer::new(1);
+        assert_eq!(push_all(&mut jitter, &[1, 3]), [Some(1)]);
+        assert_eq!(push_all(&mut jitter, &[4]), [None, Some(3), Some(4)]);
+
+        let mut jitter = JitterBuffer::new(4);
+        assert_eq!(push_all(&mut jitter, &[1, 3, 5]), [Some(1)]);
+        let mut out = Vec::new();
+        jitter.flush(&mut out);
+        assert_eq!(payloads(out), [None, Some(3), None, Some(5)]);
+    }
+
+    #[test]
+    fn a_sender_that_starts_over_is_followed() {
+        let mut jitter = JitterBuffer::new(2);
+        assert_eq!(
+            push_all(&mut jitter, &[1000, 1001]),
+            [Some(0xE8), Some(0xE9)]
+        );
+        assert_eq!(push_all(&mut jitter, &[5, 6]), [Some(5), Some(6)]);
+    }
+
+    #[test]
+    fn rtp_payloads_skip_csrcs_and_extensions_and_drop_padding() {
+        let mut packet = rtp_header(0, 42);
+        packet.extend([1, 2, 3]);
+        assert_eq!(parse_rtp(&packet), Some((42, &[1, 2, 3][..])));
+
+        // Two CSRCs.
+        let mut packet = rtp_header(2, 43);
+        packet.extend([0; 8]);
+        packet.extend([4, 5]);
+        assert_eq!(parse_rtp(&packet), Some((43, &[4, 5][..])));
+
+        // An extension one word long.
+        let mut packet = rtp_header(0x10, 44);
+        packet.extend([0xBE, 0xDE, 0, 1, 9, 9, 9, 9]);
+        packet.extend([6]);
+        assert_eq!(parse_rtp(&packet), Some((44, &[6][..])));
+
+        // Three bytes of padding, the last saying how many.
+        let mut packet = rtp_header(0x20, 45);
+        packet.extend([7, 8, 0, 0, 3]);
+        assert_eq!(parse_rtp(&packet), Some((45, &[7, 8][..])));
+    }
+
+    #[test]
+    fn anything_else_is_not_rtp() {
+        assert_eq!(parse_rtp(&[0x80; 11]), None);
+        let mut version_1 = rtp_header(0, 1);
+        version_1[0] = 0x40;
+        assert_eq!(parse_rtp(&version_1), None);
+        // More padding than packet.
+        let mut packet = rtp_header(0x20, 1);
+        packet.push(200);
+        assert_eq!(parse_rtp(&packet), None);
+        // An extension that runs off the end.
+        let mut packet = rtp_header(0x10, 1);
+        packet.extend([0, 0, 0, 4]);
+        assert_eq!(parse_rtp(&packet), None);
+    }
+}
@@ -0,0 +1,52 @@
+//! Helpers shared by the integration tests, which run the pipeline on the mock models.
+
+#![allow(dead_code)]
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use transvibe::{App, AppUpdate, PipelineState};
+
+/// Longer than any test should take, so a stuck pipeline fails instead of hanging.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An `App` fed by the returned sender. Its own commands go nowhere; tests send theirs to the
+/// pipeline directly.
+pub fn app() -> (App, mpsc::Sender<AppUpdate>) {
+    let (tx, rx) = mpsc::channel(64);
+    let (control_tx, _) = mpsc::channel(8);
+    (App::new(rx, control_tx), tx)
+}
+
+/// Applies updates to `app` until one matches `wanted`, which is applied too. `wanted` sees
+/// every update on the way, so it can also note them down.
+pub async fn wait_for(app: &mut App, mut wanted: impl FnMut(&AppUpdate) -> bool) {
+    let mut seen = Vec::new();
+    let waited = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let update = app
+                .next_update()
+                .await
+                .expect("the pipeline stopped sending updates");
+            let found = wanted(&update);
+            seen.push(format!("{update:?}"));
+            app.on_update(update);
+            if found {
+                return;
+            }
+        }
+    })
+    .await;
+    assert!(waited.is_ok(), "timed out; updates so far: {seen:#?}");
+}
+
+/// Applies updates to `app` until the pipeline reports that it has finished.
+pub async fn wait_until_finished(app: &mut App) {
+    wait_for(app, is_finished).await
+}
+
+pub fn is_finished(update: &AppUpdate) -> bool {
+    matches!(
+        update,
+        AppUpdate::PipelineStateChanged(PipelineState::Finished)
+    )
+}
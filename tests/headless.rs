@@ -0,0 +1,92 @@
+//! Headless runs, which scripts rely on to exit with the pipeline's status.
+
+mod common;
+
+use futures_util::{StreamExt, stream};
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use transvibe::headless::{self, HeadlessOutput, OutputFormat};
+use transvibe::mock::{MockTranscriber, MockTranslator};
+use transvibe::pipeline::{SpeechChunk, TranslationConfig, run_pipeline};
+use transvibe::{App, AppUpdate, TranslationStatus};
+
+/// Half a second of audio starting `start` seconds in. The mock transcriber doesn't listen to it.
+fn chunk(start: f64) -> SpeechChunk {
+    SpeechChunk {
+        samples: SamplesBuffer::new(1, 16_000, vec![0.0; 8_000]),
+        start: Duration::from_secs_f64(start),
+    }
+}
+
+#[tokio::test]
+async fn a_failed_pipeline_fails_the_run() {
+    let (mut app, tx) = common::app();
+    let mut output = HeadlessOutput::new(Vec::new(), OutputFormat::Text, false);
+    tx.send(AppUpdate::PipelineFailed("no such file".to_string()))
+        .await
+        .unwrap();
+    // The sender is still open, so only the failure can end the run.
+    let error = headless::run(&mut app, &mut output).await.unwrap_err();
+    assert_eq!(error.to_string(), "no such file");
+}
+
+#[tokio::test]
+async fn a_pipeline_that_goes_away_cleanly_ends_the_run() {
+    let (mut app, tx) = common::app();
+    let mut output = HeadlessOutput::new(Vec::new(), OutputFormat::Text, false);
+    tx.send(AppUpdate::Error("one bad packet".to_string()))
+        .await
+        .unwrap();
+    drop(tx);
+    headless::run(&mut app, &mut output).await.unwrap();
+}
+
+#[tokio::test]
+async fn stopping_translates_the_backlog_before_the_run_ends() {
+    let transcriber = MockTranscriber::new(["一つ", "二つ"]);
+    let translator = MockTranslator::new()
+        .with_entry("一つ", "one")
+        .with_entry("二つ", "two")
+        .with_delay("一つ", Duration::from_millis(200));
+    // A live capture hears both chunks straight away, then nothing until it is stopped.
+    let chunks = stream::iter([chunk(0.0), chunk(2.0)]).chain(stream::pending());
+    let mut chunks = Some(chunks);
+    let (tx, rx) = mpsc::channel(64);
+    let (control_tx, control_rx) = mpsc::channel(8);
+    let mut app = App::new(rx, control_tx);
+    let mut output = HeadlessOutput::new(Vec::new(), OutputFormat::Text, false);
+
+    let pipeline = run_pipeline(
+        |_| Ok(chunks.take().expect("the capture is only opened once")),
+        true,
+        &transcriber,
+        Some(async move { Ok(translator) }),
+        TranslationConfig::default(),
+        tx,
+        control_rx,
+    );
+    let ui = async {
+        // Ctrl+C while the first segment is still being translated.
+        let stop = tokio::time::sleep(Duration::from_millis(50));
+        headless::run_until(&mut app, &mut output, stop)
+            .await
+            .unwrap();
+        let translations: Vec<_> = app
+            .segments
+            .iter()
+            .map(|segment| (segment.status, segment.translation.clone()))
+            .collect();
+        drop(app);
+        translations
+    };
+    let (result, translations) = tokio::join!(pipeline, ui);
+    result.unwrap();
+    assert_eq!(
+        translations,
+        [
+            (TranslationStatus::Done, "one".to_string()),
+            (TranslationStatus::Done, "two".to_string()),
+        ]
+    );
+}
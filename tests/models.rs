@@ -0,0 +1,41 @@
+//! The files `--model-dir` expects, checked against what kalosm itself looks for.
+//!
+//! rwhisper keeps the files of each Whisper model private and only checks them in its default
+//! cache, so this test points that cache at a scratch directory. It changes the environment,
+//! which is why it has a test binary of its own.
+
+use kalosm::language::*;
+use kalosm::sound::*;
+use std::path::PathBuf;
+use transvibe::models::WhisperModel;
+
+#[test]
+fn whisper_files_are_the_ones_rwhisper_fetches() {
+    let data_dir = std::env::temp_dir().join(format!("transvibe-whisper-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    // SAFETY: this is the only test in this binary, so nothing else reads the environment.
+    unsafe { std::env::set_var("XDG_DATA_HOME", &data_dir) };
+    let cache = data_dir.join("kalosm").join("cache");
+
+    for model in WhisperModel::all() {
+        let builder = || WhisperBuilder::default().with_source(model.source());
+        let paths: Vec<PathBuf> = model
+            .files()
+            .iter()
+            .map(|file| file.path_in(&cache))
+            .collect();
+        for path in &paths {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        assert!(!builder().requires_download(), "{model}: {paths:?}");
+        // Each file is one rwhisper needs, not just one it doesn't mind.
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+            assert!(builder().requires_download(), "{model}: {}", path.display());
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::remove_dir_all(&cache).unwrap();
+    }
+    std::fs::remove_dir_all(data_dir).unwrap();
+}
@@ -0,0 +1,339 @@
+//! The pipeline and the `App` state machine, run together on the mock models.
+
+mod common;
+
+use futures_util::stream;
+use kalosm::sound::rodio::buffer::SamplesBuffer;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use transvibe::mock::{MockTranscriber, MockTranslator};
+use transvibe::pipeline::{
+    PipelineCommand, SpeechChunk, TranslationConfig, run_pipeline, run_source,
+};
+use transvibe::queue::{BacklogPolicy, QueueConfig};
+use transvibe::source::SyntheticSource;
+use transvibe::{AppUpdate, PipelineState, TimeSpan, TranslationStatus};
+
+/// Half a second of audio starting `start` seconds in. The mock transcriber doesn't listen to it.
+fn chunk(start: f64) -> SpeechChunk {
+    SpeechChunk {
+        samples: SamplesBuffer::new(1, 16_000, vec![0.0; 8_000]),
+        start: Duration::from_secs_f64(start),
+    }
+}
+
+/// A translator that is ready straight away.
+fn loaded(
+    translator: MockTranslator,
+) -> Option<impl Future<Output = Result<MockTranslator, anyhow::Error>> + Send + 'static> {
+    Some(async move { Ok(translator) })
+}
+
+#[tokio::test]
+async fn segments_keep_their_order_when_a_translation_is_slow() {
+    let transcriber = MockTranscriber::new(["一つ", "二つ", "三つ"]);
+    let translator = MockTranslator::new()
+        .with_entry("一つ", "one")
+        .with_entry("二つ", "two")
+        .with_entry("三つ", "three")
+        .with_delay("一つ", Duration::from_millis(200));
+    let mut chunks = Some(stream::iter([chunk(0.0), chunk(2.0), chunk(4.0)]));
+    let (mut app, tx) = common::app();
+    let (control_tx, control_rx) = mpsc::channel(8);
+
+    let pipeline = run_pipeline(
+        |_| Ok(chunks.take().expect("a finite input is only opened once")),
+        false,
+        &transcriber,
+        loaded(translator),
+        TranslationConfig::default(),
+        tx,
+        control_rx,
+    );
+    let mut completed = Vec::new();
+    let mut translated = Vec::new();
+    let ui = async {
+        common::wait_for(&mut app, |update| match update {
+            AppUpdate::SegmentComplete(segment) => {
+                completed.push(segment.id);
+                false
+            }
+            AppUpdate::Translation(id, _) => {
+                translated.push(*id);
+                false
+            }
+            update => common::is_finished(update),
+        })
+        .await;
+        drop(control_tx);
+    };
+    let (result, ()) = tokio::join!(pipeline, ui);
+    result.unwrap();
+
+    assert_eq!(completed.len(), 3);
+    assert!(completed.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(translated, completed);
+    let ids: Vec<_> = app.segments.iter().map(|segment| segment.id).collect();
+    assert_eq!(ids, completed);
+    let pairs: Vec<(&str, &str)> = app
+        .segments
+        .iter()
+        .map(|segment| (segment.source.as_str(), segment.translation.as_str()))
+        .collect();
+    assert_eq!(pairs, [("一つ", "one"), ("二つ", "two"), ("三つ", "three")]);
+    assert!(
+        app.segments
+            .iter()
+            .all(|segment| segment.status == TranslationStatus::Done)
+    );
+    assert_eq!(
+        app.segments[1].time,
+        Some(TimeSpan {
+            start: Duration::from_secs(2),
+            end: Duration::from_millis(2_500),
+        })
+    );
+}
+
+#[tokio::test]
+async fn pausing_closes_a_live_capture_and_resuming_reopens_it() {
+    let transcriber = MockTranscriber::new(["前", "後"]);
+    // Every time the capture is opened, the test gets the sender for its audio.
+    let (opened_tx, mut opened) = mpsc::unbounded_channel();
+    let (mut app, tx) = common::app();
+    let (control_tx, control_rx) = mpsc::channel(8);
+
+    let pipeline = run_pipeline(
+        move |_| {
+            let (chunks_tx, mut chunks) = mpsc::channel(8);
+            opened_tx.send(chunks_tx).unwrap();
+            Ok(stream::poll_fn(move |cx| chunks.poll_recv(cx)))
+        },
+        true,
+        &transcriber,
+        loaded(MockTranslator::new()),
+        TranslationConfig::default(),
+        tx,
+        control_rx,
+    );
+    let is_translation = |update: &AppUpdate| matches!(update, AppUpdate::Translation(..));
+    let script = async {
+        let first = opened.recv().await.unwrap();
+        first.send(chunk(0.0)).await.unwrap();
+        common::wait_for(&mut app, is_translation).await;
+
+        control_tx.send(PipelineCommand::Pause).await.unwrap();
+        common::wait_for(&mut app, |update| {
+            matches!(
+                update,
+                AppUpdate::PipelineStateChanged(PipelineState::Paused)
+            )
+        })
+        .await;
+        assert_eq!(app.pipeline_state, PipelineState::Paused);
+        // The capture is closed, so nothing said while paused reaches the transcriber.
+        assert!(first.send(chunk(1.0)).await.is_err());
+
+        // Typed text is still translated while paused.
+        control_tx
+            .send(PipelineCommand::TranslateText("入力".to_string()))
+            .await
+            .unwrap();
+        common::wait_for(&mut app, is_translation).await;
+
+        control_tx.send(PipelineCommand::Resume).await.unwrap();
+        common::wait_for(&mut app, |update| {
+            matches!(
+                update,
+                AppUpdate::PipelineStateChanged(PipelineState::Listening)
+            )
+        })
+        .await;
+        let second = opened.recv().await.expect("resuming reopens the capture");
+        second.send(chunk(5.0)).await.unwrap();
+        common::wait_for(&mut app, is_translation).await;
+
+        drop(second); // The input ends.
+        common::wait_until_finished(&mut app).await;
+        drop(control_tx);
+    };
+    let (result, ()) = tokio::join!(pipeline, script);
+    result.unwrap();
+
+    let sources: Vec<&str> = app
+        .segments
+        .iter()
+        .map(|segment| segment.source.as_str())
+        .collect();
+    assert_eq!(sources, ["前", "入力", "後"]);
+    assert!(app.segments.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert_eq!(app.segments[1].time, None);
+    assert_eq!(
+        app.segments[2].time.map(|time| time.start),
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(app.segments[2].translation, "[en] 後");
+}
+
+#[tokio::test]
+async fn typed_text_is_translated_after_the_input_ends() {
+    let transcriber = MockTranscriber::new(["音声"]);
+    let translator = MockTranslator::new()
+        .with_entry("音声", "audio")
+        .with_entry("こんにちは", "Hello");
+    let mut chunks = Some(stream::iter([chunk(0.0)]));
+    let (mut app, tx) = common::app();
+    let (control_tx, control_rx) = mpsc::channel(8);
+
+    let pipeline = run_pipeline(
+        |_| Ok(chunks.take().expect("a finite input is only opened once")),
+        false,
+        &transcriber,
+        loaded(translator),
+        TranslationConfig::default(),
+        tx,
+        control_rx,
+    );
+    let script = async {
+        common::wait_until_finished(&mut app).await;
+        // Blank lines are not worth a segment.
+        for text in ["   ", "こんにちは"] {
+            control_tx
+                .send(PipelineCommand::TranslateText(text.to_string()))
+                .await
+                .unwrap();
+        }
+        common::wait_for(&mut app, |update| {
+            matches!(update, AppUpdate::Translation(..))
+        })
+        .await;
+        drop(control_tx);
+    };
+    let (result, ()) = tokio::join!(pipeline, script);
+    result.unwrap();
+
+    assert_eq!(app.segments.len(), 2);
+    let typed = &app.segments[1];
+    assert!(typed.id > app.segments[0].id);
+    assert_eq!(typed.source, "こんにちは");
+    assert_eq!(typed.translation, "Hello");
+    assert_eq!(typed.status, TranslationStatus::Done);
+    assert_eq!(typed.time, None);
+}
+
+#[tokio::test]
+async fn the_backlog_from_while_the_translator_loads_is_merged_to_fit() {
+    let transcriber = MockTranscriber::new(["一", "二", "三", "四"]);
+    let (load_tx, load_rx) = tokio::sync::oneshot::channel();
+    let translator = async move {
+        load_rx.await?;
+        Ok::<_, anyhow::Error>(MockTranslator::new())
+    };
+    let (chunks_tx, mut chunks) = mpsc::channel(8);
+    let mut chunks = Some(stream::poll_fn(move |cx| chunks.poll_recv(cx)));
+    let (mut app, tx) = common::app();
+    let (control_tx, control_rx) = mpsc::channel(8);
+    let config = TranslationConfig {
+        queue: QueueConfig {
+            capacity: 2,
+            policy: BacklogPolicy::Merge,
+        },
+        ..TranslationConfig::default()
+    };
+
+    let pipeline = run_pipeline(
+        |_| Ok(chunks.take().expect("the capture is never paused")),
+        true,
+        &transcriber,
+        Some(translator),
+        config,
+        tx,
+        control_rx,
+    );
+    let script = async {
+        for start in [0.0, 1.0, 2.0, 3.0] {
+            chunks_tx.send(chunk(start)).await.unwrap();
+        }
+        // All four are queued, past the capacity of two, while the translator loads.
+        common::wait_for(&mut app, |update| {
+            matches!(update, AppUpdate::TranslationQueueDepth(4))
+        })
+        .await;
+        load_tx.send(()).unwrap();
+        drop(chunks_tx);
+        common::wait_until_finished(&mut app).await;
+        drop(control_tx);
+    };
+    let (result, ()) = tokio::join!(pipeline, script);
+    result.unwrap();
+
+    let pairs: Vec<(&str, &str)> = app
+        .segments
+        .iter()
+        .map(|segment| (segment.source.as_str(), segment.translation.as_str()))
+        .collect();
+    assert_eq!(pairs, [("一", "[en] 一"), ("二 三 四", "[en] 二 三 四")]);
+    assert_eq!(
+        app.segments[1].time,
+        Some(TimeSpan {
+            start: Duration::from_secs(1),
+            end: Duration::from_millis(3_500),
+        })
+    );
+}
+
+/// Whether `actual` is within `tolerance` of `expected` seconds.
+fn near(actual: Duration, expected: f64, tolerance: f64) -> bool {
+    (actual.as_secs_f64() - expected).abs() <= tolerance
+}
+
+#[tokio::test]
+async fn a_synthetic_source_runs_through_voice_detection_to_the_end() {
+    let second = Duration::from_secs(1);
+    let source = SyntheticSource::new(16_000)
+        .silence(second)
+        .tone(440.0, second)
+        .silence(2 * second)
+        .tone(660.0, second)
+        .silence(2 * second)
+        .with_speech_level(0.1);
+    let transcriber = MockTranscriber::new(["一つ", "二つ"]);
+    let translator = MockTranslator::new()
+        .with_entry("一つ", "one")
+        .with_entry("二つ", "two");
+    let (mut app, tx) = common::app();
+    let (control_tx, control_rx) = mpsc::channel(8);
+
+    let pipeline = run_source(
+        source,
+        Duration::from_secs(10),
+        &transcriber,
+        loaded(translator),
+        TranslationConfig::default(),
+        tx,
+        control_rx,
+    );
+    let script = async {
+        common::wait_until_finished(&mut app).await;
+        drop(control_tx);
+    };
+    let (result, ()) = tokio::join!(pipeline, script);
+    result.unwrap();
+
+    let pairs: Vec<(&str, &str)> = app
+        .segments
+        .iter()
+        .map(|segment| (segment.source.as_str(), segment.translation.as_str()))
+        .collect();
+    assert_eq!(pairs, [("一つ", "one"), ("二つ", "two")]);
+    // Each tone, give or take the speech kept before it and the silence it takes to end it,
+    // on the clock offset by ten seconds.
+    for (segment, tone_start) in app.segments.iter().zip([11.0, 14.0]) {
+        let time = segment.time.expect("audio segments are timed");
+        assert!(near(time.start, tone_start, 0.3), "{time:?}");
+        assert!(
+            time.end > time.start && near(time.end, tone_start + 1.0, 0.6),
+            "{time:?}"
+        );
+    }
+}